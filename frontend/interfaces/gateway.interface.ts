@@ -4,18 +4,77 @@ type Player = {
   steamid: string; // this should be considered unique
 };
 
+type UpdateStage = "reconfiguring" | "preallocating" | "downloading" | "verifying" | "committing" | "other";
+
+type UpdateErrorKind = "missing_configuration" | "disk_space" | "rate_limited" | "login_failed" | "other";
+
+type SteamPrompt = "password" | "steam_guard_code" | "two_factor_code" | "mobile_confirmation";
+
+type SteamAccount = {
+  username: string;
+};
+
 type UpdateSteamMessage = {
   type: "steam_self_update";
   status: string;
+  progress: number | null; // percent
+} | {
+  type: "logged_in";
+  user: string | null; // null if anonymous
+} | {
+  type: "prompt"; // answer with { type: "input", text }
+  prompt: SteamPrompt;
 } | {
   type: "update_state";
   state_id: number;
   state_name: string;
-  progress: string;
+  stage: UpdateStage;
+  progress: number; // percent
   current: number;
   total: number;
 } | {
   type: "success"
 } | {
-  type: "error"; reason: string
+  type: "error"; reason: string; kind: UpdateErrorKind
+} | {
+  type: "cancelled"
+} | {
+  type: "warning"; reason: string // from the gateway, e.g. when the disk space was not checked
+} | {
+  type: "finished"
+} & JobResult;
+
+type JobStatus = {
+  status: "running"
+} | {
+  status: "exited";
+  code: number | null;
+  signal: number | null; // set if killed by a signal
+} | {
+  status: "cancelled"
+} | {
+  status: "failed";
+  reason: string;
+};
+
+type JobResult = JobStatus & {
+  duration: number; // seconds
+  errors: string[]; // last lines SteamCMD reported an error in
+};
+
+type FieldError = {
+  key: string;
+  value: string;
+  message: string;
+};
+
+type SettingSchema = {
+  key: string;
+  type: "float" | "integer" | "boolean" | "string" | "enum";
+  default: number | boolean | string | null;
+  min: number | null;
+  max: number | null;
+  values: string[] | null;
+  unit: string | null;
+  description: string;
 };
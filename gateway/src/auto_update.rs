@@ -0,0 +1,192 @@
+use std::{sync::Arc, time::Duration};
+
+use thiserror::Error;
+use tokio::time::{sleep, Instant};
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    pal::{self, PalServerClient, PalworldCommandError},
+    steamcmd::{
+        disk::disk_report,
+        job::{Job, JobManager},
+        update_check, SteamCMDError, UpdateType,
+    },
+};
+
+/// Broadcast to players this many minutes before the deadline.
+const WARNING_MINUTES: &[u64] = &[30, 15, 10, 5, 1];
+const PLAYER_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Players get this long between the shutdown message and the shutdown.
+const SHUTDOWN_SECONDS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct AutoUpdateConfig {
+    /// Between checks for a new build.
+    pub interval: Duration,
+    /// How long to wait for the server to empty before updating anyway.
+    pub deadline: Duration,
+    /// Starts the server after updating, see [`pal::start_server`].
+    pub start_command: String,
+}
+
+#[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
+enum AutoUpdateError {
+    #[error("error from the inner RCON client")]
+    PalworldCommandError(#[from] PalworldCommandError),
+    #[error("error from SteamCMD")]
+    SteamCMDError(#[from] SteamCMDError),
+    #[error("error running the start command")]
+    IOError(#[from] std::io::Error),
+    #[error("the server is still up {0:?} after shutting down")]
+    StillRunning(Duration),
+}
+
+/// Checks for a new build every `interval` and installs it, for as long as the gateway runs.
+pub fn spawn(config: AutoUpdateConfig, client: PalServerClient, jobs: JobManager) {
+    info!(
+        "checking for updates every {:?}, waiting up to {:?} for players to leave",
+        config.interval, config.deadline
+    );
+    tokio::spawn(async move {
+        loop {
+            sleep(config.interval).await;
+            if let Err(err) = check_and_update(&config, client.clone(), &jobs).await {
+                error!("automatic update failed: {:?}", err);
+            }
+        }
+    });
+}
+
+#[instrument(skip_all)]
+async fn check_and_update(
+    config: &AutoUpdateConfig,
+    mut client: PalServerClient,
+    jobs: &JobManager,
+) -> Result<(), AutoUpdateError> {
+    let app_info = run_job(jobs, UpdateType::AppInfo)
+        .await?
+        .successful_output()
+        .await?;
+    let check = update_check(jobs.paths(), &app_info).await?;
+    let Some(installed) = check.installed_build_id.filter(|_| check.update_available) else {
+        info!("no update available");
+        return Ok(());
+    };
+    info!(
+        "build {} available, installed is {}",
+        check.latest_build_id, installed
+    );
+    // better to keep the server up than to find out after shutting it down
+    if check.password_required {
+        warn!(
+            "branch {} needs a password, update it by hand instead",
+            check.branch
+        );
+        return Ok(());
+    }
+    let update_type = UpdateType::Game {
+        validate: false,
+        branch: Some(check.branch),
+        branch_password: None,
+    };
+    disk_report(jobs.paths(), Some(&app_info), None)
+        .await?
+        .check(&update_type)?;
+
+    wait_for_players(config.deadline, &mut client).await;
+
+    info!("saving: {}", client.save().await?.trim());
+    info!(
+        "shutting down: {}",
+        client
+            .shutdown(SHUTDOWN_SECONDS, "Server_updating_now")
+            .await?
+            .trim()
+    );
+    let limit = Duration::from_secs(SHUTDOWN_SECONDS as u64 + 60);
+    if !client.wait_for_exit(limit).await {
+        return Err(AutoUpdateError::StillRunning(limit));
+    }
+
+    info!("server exited, updating");
+    let job = run_job(jobs, update_type).await?;
+    let result = job.result();
+    if result.is_success() {
+        info!("update job {} succeeded", job.id);
+    } else {
+        warn!("update job {} ended with {:?}", job.id, result);
+    }
+
+    // the old build is better than none if the update failed
+    info!("starting the server with `{}`", config.start_command);
+    pal::start_server(&config.start_command)?;
+    Ok(())
+}
+
+/// Waits for another job first if SteamCMD is busy, then runs one to the end.
+async fn run_job(jobs: &JobManager, update_type: UpdateType) -> Result<Arc<Job>, SteamCMDError> {
+    loop {
+        match jobs.start(update_type.clone()) {
+            Ok(job) => {
+                job.finished().await;
+                return Ok(job);
+            }
+            Err(SteamCMDError::Busy(id)) => {
+                info!("waiting for job {} to finish", id);
+                if let Some(job) = jobs.get(id) {
+                    job.finished().await;
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Returns once the server is empty (or unreachable) or `deadline` has passed, warning players
+/// along the way.
+async fn wait_for_players(deadline: Duration, client: &mut PalServerClient) {
+    let until = Instant::now() + deadline;
+    let mut warnings = WARNING_MINUTES.iter().peekable();
+    let mut first = true;
+    loop {
+        match client.player_count().await {
+            Ok(0) => {
+                info!("server is empty");
+                return;
+            }
+            Ok(count) => info!("{} players online", count),
+            Err(err) => {
+                warn!("failed to count players, updating anyway: {:?}", err);
+                return;
+            }
+        }
+
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            info!("deadline reached, updating with players online");
+            return;
+        }
+        let minutes = left.as_secs().div_ceil(60);
+        let mut due = first;
+        while warnings.next_if(|&&m| m >= minutes).is_some() {
+            due = true;
+        }
+        if due {
+            let message = if first {
+                format!(
+                    "New_version_available,_the_server_will_update_when_empty_or_in_{}_minutes",
+                    minutes
+                )
+            } else {
+                format!("Server_will_update_in_{}_minutes", minutes)
+            };
+            if let Err(err) = client.broadcast(message).await {
+                warn!("failed to warn players: {:?}", err);
+            }
+            first = false;
+        }
+
+        sleep(PLAYER_POLL_INTERVAL.min(left)).await;
+    }
+}
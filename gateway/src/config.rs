@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::paths::Paths;
+
+/// Read if it exists and no other file is given.
+const DEFAULT_CONFIG_FILE: &str = "palboard.toml";
+const REDACTED: &str = "<redacted>";
+
+/// Every flag can also be set by its environment variable or in the config file, flags win over
+/// the environment, which wins over the file.
+#[derive(Debug, Parser)]
+#[command(about = "Gateway between palboard and a Palworld server")]
+pub struct Args {
+    /// TOML config file, `palboard.toml` is read if it exists
+    #[arg(short, long, env = "PALBOARD_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on, the frontend connects to it [default: 127.0.0.1:8080]
+    #[arg(long, env = "GATEWAY_ADDR")]
+    gateway_addr: Option<String>,
+    /// RCON address, leave out the port to follow `RCONPort` [default: 127.0.0.1]
+    #[arg(long, env = "PALSERVER_ADDR")]
+    palserver_addr: Option<String>,
+    /// RCON password [default: `AdminPassword`]
+    #[arg(long, env = "PALSERVER_PASSWORD", hide_env_values = true)]
+    palserver_password: Option<String>,
+    /// Starts the server after applying config changes or updating, e.g. `systemctl start palserver`
+    #[arg(long, env = "PALSERVER_START_COMMAND")]
+    start_command: Option<String>,
+    /// Where SteamCMD installs the server [default: /home/steam/palserver]
+    #[arg(long, env = "PALSERVER_DIR")]
+    install_dir: Option<PathBuf>,
+    /// [default: <install dir>/Pal/Saved/Config/LinuxServer]
+    #[arg(long, env = "PALSERVER_CONFIG_DIR")]
+    config_dir: Option<PathBuf>,
+    /// [default: <install dir>/Pal/Saved/SaveGames]
+    #[arg(long, env = "PALSERVER_SAVE_DIR")]
+    save_dir: Option<PathBuf>,
+    /// Config history and presets [default: <install dir>/palboard]
+    #[arg(long, env = "PALBOARD_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// [default: /home/steam/steamcmd/steamcmd.sh]
+    #[arg(long, env = "STEAMCMD_PATH")]
+    steamcmd: Option<PathBuf>,
+    /// Looked up in /bin and /usr/bin by default, empty to run SteamCMD without it
+    #[arg(long, env = "STDBUF_PATH")]
+    stdbuf: Option<String>,
+    /// Check for game updates this often and install them, needs a start command
+    #[arg(long, env = "AUTO_UPDATE_INTERVAL_MINUTES")]
+    auto_update_interval_minutes: Option<u64>,
+    /// How long to wait for players to leave before updating anyway [default: 30]
+    #[arg(long, env = "AUTO_UPDATE_DEADLINE_MINUTES")]
+    auto_update_deadline_minutes: Option<u64>,
+}
+
+/// Layout of the config file, everything is optional.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub gateway: GatewayConfig,
+    pub palserver: PalServerConfig,
+    pub paths: PathsConfig,
+    pub auto_update: AutoUpdateSection,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatewayConfig {
+    pub addr: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PalServerConfig {
+    pub addr: Option<String>,
+    pub password: Option<String>,
+    pub start_command: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    pub install_dir: Option<PathBuf>,
+    pub config_dir: Option<PathBuf>,
+    pub save_dir: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub steamcmd: Option<PathBuf>,
+    /// Empty to run SteamCMD without stdbuf.
+    pub stdbuf: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoUpdateSection {
+    /// Disabled unless set.
+    pub interval_minutes: Option<u64>,
+    pub deadline_minutes: Option<u64>,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid `{key}`: {message}")]
+    Invalid { key: &'static str, message: String },
+}
+
+fn override_with<T>(field: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *field = value;
+    }
+}
+
+impl Config {
+    /// Layers `args` over the config file, then validates the result.
+    pub fn load(args: Args) -> Result<Config, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Config::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Config::read(DEFAULT_CONFIG_FILE)?,
+            None => Config::default(),
+        };
+
+        override_with(&mut config.gateway.addr, args.gateway_addr);
+        override_with(&mut config.palserver.addr, args.palserver_addr);
+        override_with(&mut config.palserver.password, args.palserver_password);
+        override_with(&mut config.palserver.start_command, args.start_command);
+        override_with(&mut config.paths.install_dir, args.install_dir);
+        override_with(&mut config.paths.config_dir, args.config_dir);
+        override_with(&mut config.paths.save_dir, args.save_dir);
+        override_with(&mut config.paths.data_dir, args.data_dir);
+        override_with(&mut config.paths.steamcmd, args.steamcmd);
+        override_with(&mut config.paths.stdbuf, args.stdbuf);
+        override_with(
+            &mut config.auto_update.interval_minutes,
+            args.auto_update_interval_minutes,
+        );
+        override_with(
+            &mut config.auto_update.deadline_minutes,
+            args.auto_update_deadline_minutes,
+        );
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        toml::from_str(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key, message: &str| ConfigError::Invalid {
+            key,
+            message: message.to_string(),
+        };
+
+        // both may be host names
+        match self.gateway.addr.as_ref().map(|a| a.rsplit_once(':')) {
+            Some(Some((_, port))) if port.parse::<u16>().is_ok() => {}
+            Some(_) => return Err(invalid("gateway.addr", "expected `host:port`")),
+            None => {}
+        }
+        if let Some((_, port)) = self
+            .palserver
+            .addr
+            .as_ref()
+            .and_then(|a| a.rsplit_once(':'))
+        {
+            port.parse::<u16>()
+                .map_err(|_| invalid("palserver.addr", &format!("`{}` is not a port", port)))?;
+        }
+        if self.palserver.start_command.as_deref() == Some("") {
+            return Err(invalid(
+                "palserver.start_command",
+                "empty, leave it out instead",
+            ));
+        }
+
+        match self.auto_update.interval_minutes {
+            Some(0) => {
+                return Err(invalid(
+                    "auto_update.interval_minutes",
+                    "must be at least 1",
+                ))
+            }
+            Some(_) if self.palserver.start_command.is_none() => {
+                return Err(invalid(
+                    "auto_update.interval_minutes",
+                    "automatic updates need `palserver.start_command` to start the server again",
+                ))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn paths(&self) -> Paths {
+        let config = &self.paths;
+        let mut paths = Paths::new(
+            config
+                .install_dir
+                .clone()
+                .unwrap_or_else(|| "/home/steam/palserver".into()),
+        );
+        if let Some(config_dir) = &config.config_dir {
+            paths.config_dir = config_dir.clone();
+        }
+        if let Some(save_dir) = &config.save_dir {
+            paths.save_dir = save_dir.clone();
+        }
+        if let Some(data_dir) = &config.data_dir {
+            paths.data_dir = data_dir.clone();
+        }
+        if let Some(steamcmd) = &config.steamcmd {
+            paths.steamcmd = steamcmd.clone();
+        }
+        if let Some(stdbuf) = &config.stdbuf {
+            paths.stdbuf = (!stdbuf.is_empty()).then(|| stdbuf.into());
+        }
+        paths
+    }
+
+    /// For showing to clients, without the RCON password.
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        if config.palserver.password.is_some() {
+            config.palserver.password = Some(REDACTED.to_string());
+        }
+        config
+    }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    settings::{load_ini, write_ini},
+    GameConfigError,
+};
+
+/// Config files next to `PalWorldSettings.ini` that may be edited, named without `.ini`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ConfigFile {
+    Engine,
+    GameUserSettings,
+}
+
+impl ConfigFile {
+    pub const ALL: [ConfigFile; 2] = [ConfigFile::Engine, ConfigFile::GameUserSettings];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigFile::Engine => "Engine",
+            ConfigFile::GameUserSettings => "GameUserSettings",
+        }
+    }
+
+    pub fn file_name(self) -> String {
+        format!("{}.ini", self.name())
+    }
+}
+
+/// Checks that `content` is an INI document Unreal can read.
+pub fn validate(content: &str) -> Result<(), GameConfigError> {
+    load_ini(content).map(drop)
+}
+
+/// A known set of values that is commonly changed together.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tweak {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub file: ConfigFile,
+    pub section: &'static str,
+    pub values: &'static [(&'static str, &'static str)],
+}
+
+pub const TWEAKS: &[Tweak] = &[
+    Tweak {
+        name: "tick_rate",
+        description: "Let the server tick up to 120 times per second instead of 30",
+        file: ConfigFile::Engine,
+        section: "/Script/OnlineSubsystemUtils.IpNetDriver",
+        values: &[
+            ("NetServerMaxTickRate", "120"),
+            ("LanServerMaxTickRate", "120"),
+        ],
+    },
+    Tweak {
+        name: "frame_rate",
+        description: "Smooth the server frame rate between 30 and 120",
+        file: ConfigFile::Engine,
+        section: "/Script/Engine.Engine",
+        values: &[
+            ("bSmoothFrameRate", "true"),
+            ("bUseFixedFrameRate", "false"),
+            (
+                "SmoothedFrameRateRange",
+                "(LowerBound=(Type=Inclusive,Value=30.000000),UpperBound=(Type=Exclusive,Value=120.000000))",
+            ),
+            ("MinDesiredFrameRate", "60.000000"),
+            ("NetClientTicksPerSecond", "120"),
+        ],
+    },
+    Tweak {
+        name: "client_rate",
+        description: "Raise the bandwidth limit per client, which helps with many players or bases",
+        file: ConfigFile::Engine,
+        section: "/Script/OnlineSubsystemUtils.IpNetDriver",
+        values: &[
+            ("MaxClientRate", "104857600"),
+            ("MaxInternetClientRate", "104857600"),
+        ],
+    },
+    Tweak {
+        name: "player_speed",
+        description: "Raise the assumed connection speed of players",
+        file: ConfigFile::Engine,
+        section: "/Script/Engine.Player",
+        values: &[
+            ("ConfiguredInternetSpeed", "104857600"),
+            ("ConfiguredLanSpeed", "104857600"),
+        ],
+    },
+];
+
+pub fn find_tweak(name: &str) -> Result<&'static Tweak, GameConfigError> {
+    TWEAKS
+        .iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| GameConfigError::TweakNotFound(name.to_string()))
+}
+
+/// Sets the tweak's values, in an existing section if there is one since Unreal ignores case.
+pub fn apply_tweak(content: &str, tweak: &Tweak) -> Result<String, GameConfigError> {
+    let mut ini = load_ini(content)?;
+    let section = ini
+        .sections()
+        .flatten()
+        .find(|s| s.eq_ignore_ascii_case(tweak.section))
+        .unwrap_or(tweak.section)
+        .to_string();
+    for (key, value) in tweak.values {
+        ini.with_section(Some(section.as_str())).set(*key, *value);
+    }
+    Ok(write_ini(&ini))
+}
@@ -0,0 +1,83 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::read_optional;
+
+/// An archived version of a config file, replaced by `author` at `id` (Unix milliseconds).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionInfo {
+    pub id: u64,
+    pub author: String,
+}
+
+/// Previous versions of a single config file, stored as `<id>.ini` and `<id>.json` in `dir`.
+#[derive(Debug, Clone)]
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        History { dir: dir.into() }
+    }
+
+    fn content_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.ini", id))
+    }
+
+    fn info_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Stores `content` of a file that is about to be overwritten.
+    pub async fn archive(
+        &self,
+        content: impl AsRef<[u8]>,
+        author: impl ToString,
+    ) -> std::io::Result<VersionInfo> {
+        fs::create_dir_all(&self.dir).await?;
+
+        let mut id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        while fs::try_exists(self.info_path(id)).await? {
+            id += 1;
+        }
+        let info = VersionInfo {
+            id,
+            author: author.to_string(),
+        };
+        fs::write(self.content_path(id), content).await?;
+        fs::write(self.info_path(id), serde_json::to_vec(&info)?).await?;
+        Ok(info)
+    }
+
+    /// All archived versions, newest first.
+    pub async fn list(&self) -> std::io::Result<Vec<VersionInfo>> {
+        let mut versions = Vec::new();
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(versions),
+            Err(err) => return Err(err),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                versions.push(serde_json::from_slice(&fs::read(path).await?)?);
+            }
+        }
+        versions.sort_by_key(|v: &VersionInfo| std::cmp::Reverse(v.id));
+        Ok(versions)
+    }
+
+    /// Content of an archived version, `None` if there is no such version.
+    pub async fn read(&self, id: u64) -> std::io::Result<Option<String>> {
+        read_optional(self.content_path(id)).await
+    }
+}
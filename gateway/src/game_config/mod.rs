@@ -1,42 +1,121 @@
-mod unreal_struct;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+};
 
-pub mod route {
-    use std::path::Path;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use thiserror::Error;
+use tokio::{fs, io::AsyncWriteExt};
 
-    use axum::{routing::post, Router};
-    use tower_http::services::ServeFile;
+use settings::{FieldError, OPTION_SETTINGS};
 
-    use crate::AppResult;
+pub mod files;
+pub mod history;
+pub mod preset;
+pub mod route;
+pub mod running;
+pub mod settings;
+pub mod unreal_struct;
 
-    pub fn new_router(base_path: impl AsRef<Path>) -> Router<()> {
-        let base_path = base_path.as_ref();
-        let default_path = base_path.join("DefaultPalWorldSettings.ini");
-        let current_path = base_path.join("Pal/Saved/Config/LinuxServer/PalWorldSettings.ini");
-        Router::new()
-            .route_service("/default", ServeFile::new(&default_path))
-            .route_service("/current", ServeFile::new(&current_path))
-            .route("/save", post(|body| save(body, current_path)))
+/// Where the server keeps its config files, relative to its install directory.
+pub const CONFIG_DIR: &str = "Pal/Saved/Config/LinuxServer";
+
+#[derive(Error, Debug)]
+pub enum GameConfigError {
+    #[error("failed to parse INI: {0}")]
+    IniError(#[from] ini::ParseError),
+    #[error("failed to parse {OPTION_SETTINGS}: {0}")]
+    StructError(#[from] unreal_struct::ParseError),
+    #[error("failed to write {OPTION_SETTINGS}: {0}")]
+    SerializeError(#[from] unreal_struct::SerializeError),
+    #[error("invalid value `{value}` for `{key}`")]
+    InvalidValue { key: String, value: String },
+    #[error("{} invalid settings", .0.len())]
+    Invalid(Vec<FieldError>),
+    #[error("version {0} not found in history")]
+    VersionNotFound(u64),
+    #[error("the file was changed by someone else, its version is now {current}")]
+    VersionMismatch { current: String },
+    #[error("`If-Match` (or `If-None-Match: *` to create the file) is required")]
+    VersionRequired,
+    #[error("preset name `{0}` may only contain letters, digits, `_` and `-`")]
+    InvalidPresetName(String),
+    #[error("preset `{0}` not found")]
+    PresetNotFound(String),
+    #[error("tweak `{0}` not found")]
+    TweakNotFound(String),
+    #[error("error during IO")]
+    IOError(#[from] std::io::Error),
+}
+
+impl IntoResponse for GameConfigError {
+    fn into_response(self) -> Response {
+        match self {
+            GameConfigError::Invalid(errors) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response()
+            }
+            GameConfigError::VersionNotFound(_)
+            | GameConfigError::PresetNotFound(_)
+            | GameConfigError::TweakNotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
+            GameConfigError::IOError(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {:?}", self),
+            )
+                .into_response(),
+            GameConfigError::VersionMismatch { .. } => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            GameConfigError::VersionRequired => {
+                (StatusCode::PRECONDITION_REQUIRED, self.to_string()).into_response()
+            }
+            _ => (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()).into_response(),
+        }
     }
+}
+
+/// Quoted entity tag of a config file's content, for optimistic concurrency.
+pub fn etag(content: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
 
-    async fn save(body: String, path: impl AsRef<Path>) -> AppResult<()> {
-        tokio::fs::create_dir_all(path.as_ref().parent().unwrap()).await?;
-        tokio::fs::write(path, body).await?;
-        Ok(())
+/// Reads a file, `None` if it does not exist.
+pub async fn read_optional(path: impl AsRef<Path>) -> std::io::Result<Option<String>> {
+    match fs::read_to_string(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
     }
 }
 
-// fn parse_ini() {
-//     let i = Ini::load_from_file("/Users/shiroki/Downloads/DefaultPalWorldSettings.ini").unwrap();
-//     for (sec, prop) in i.iter() {
-//         println!("Section: {:?}", sec);
-//         for (k, v) in prop.iter() {
-//             // println!("{}:{}", k, v);
-//             if v.starts_with('(') && v.ends_with(')') {
-//                 // assume as Unreal config struct
-//                 println!("{k}: {:?}", unreal_struct::parse_struct(v));
-//             } else {
-//                 println!("{k}: {v}");
-//             }
-//         }
-//     }
-// }
+/// Writes to a temporary file next to `path` and renames it over, so a crash never leaves a
+/// truncated file behind.
+pub async fn write_atomically(
+    path: impl AsRef<Path>,
+    content: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir).await?;
+
+    let mut tmp_name = path.file_name().unwrap().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = dir.join(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path).await?;
+    file.write_all(content.as_ref()).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    fs::rename(&tmp_path, path).await?;
+    // persist the rename itself
+    fs::File::open(dir).await?.sync_all().await?;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use tokio::fs;
+
+use super::{
+    read_optional,
+    unreal_struct::{parse_struct, serialize_struct, Value},
+    GameConfigError,
+};
+
+/// Named partial `OptionSettings`, stored as Unreal structs in `<dir>/<name>.txt`.
+#[derive(Debug, Clone)]
+pub struct Presets {
+    dir: PathBuf,
+}
+
+fn check_name(name: &str) -> Result<(), GameConfigError> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        Ok(())
+    } else {
+        Err(GameConfigError::InvalidPresetName(name.to_string()))
+    }
+}
+
+impl Presets {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Presets { dir: dir.into() }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, GameConfigError> {
+        check_name(name)?;
+        Ok(self.dir.join(format!("{}.txt", name)))
+    }
+
+    /// Names of all presets, sorted.
+    pub async fn list(&self) -> std::io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+            Err(err) => return Err(err),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "txt") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub async fn read(&self, name: &str) -> Result<Vec<(String, Value)>, GameConfigError> {
+        let content = read_optional(self.path(name)?)
+            .await?
+            .ok_or_else(|| GameConfigError::PresetNotFound(name.to_string()))?;
+        Ok(parse_struct(content.trim())?)
+    }
+
+    pub async fn write(
+        &self,
+        name: &str,
+        props: &[(String, Value)],
+    ) -> Result<(), GameConfigError> {
+        let path = self.path(name)?;
+        fs::create_dir_all(&self.dir).await?;
+        fs::write(path, serialize_struct(props)?).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, name: &str) -> Result<(), GameConfigError> {
+        match fs::remove_file(self.path(name)?).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(GameConfigError::PresetNotFound(name.to_string()))
+            }
+            res => Ok(res?),
+        }
+    }
+}
+
+/// Sets every key of `overlay` in `props`, in place if it exists and appended otherwise.
+pub fn apply_overlay(
+    mut props: Vec<(String, Value)>,
+    overlay: &[(String, Value)],
+) -> Vec<(String, Value)> {
+    for (key, value) in overlay {
+        match props.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.clone(),
+            None => props.push((key.clone(), value.clone())),
+        }
+    }
+    props
+}
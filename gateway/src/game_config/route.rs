@@ -0,0 +1,611 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{
+        header::{ETAG, IF_MATCH, IF_NONE_MATCH},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tower_http::services::ServeFile;
+use tracing::{error, info, warn};
+
+use crate::{
+    pal::{self, PalServerClient},
+    paths::Paths,
+    AppResult,
+};
+
+use super::{
+    etag,
+    files::{self, apply_tweak, find_tweak, ConfigFile, Tweak, TWEAKS},
+    history::{History, VersionInfo},
+    preset::{apply_overlay, Presets},
+    read_optional,
+    running::RunningSettings,
+    settings::{
+        diff_ini, option_settings_props, with_option_settings, PalWorldSettings, PropDiff,
+        SettingSchema,
+    },
+    unreal_struct::{parse_struct, serialize_struct},
+    write_atomically, GameConfigError,
+};
+
+#[derive(Debug)]
+struct GameConfig {
+    default_path: PathBuf,
+    current_path: PathBuf,
+    history: History,
+    /// `Pal/Saved/Config/LinuxServer`, where the other [`ConfigFile`]s are.
+    config_dir: PathBuf,
+    history_dir: PathBuf,
+    presets: Presets,
+    /// Held from reading the current file until it is replaced.
+    write_lock: Mutex<()>,
+    client: PalServerClient,
+    running: RunningSettings,
+    /// Starts the server again after applying, otherwise left to e.g. a restart policy.
+    start_command: Option<String>,
+}
+
+pub fn new_router(
+    paths: &Paths,
+    client: PalServerClient,
+    start_command: Option<String>,
+    running: RunningSettings,
+) -> Router<()> {
+    let history_dir = paths.data_dir.join("history");
+    let current_path = paths.settings_file();
+    let config = GameConfig {
+        default_path: paths.default_settings_file(),
+        current_path,
+        history: History::new(history_dir.join("PalWorldSettings")),
+        config_dir: paths.config_dir.clone(),
+        history_dir,
+        presets: Presets::new(paths.data_dir.join("presets")),
+        write_lock: Mutex::new(()),
+        client,
+        running,
+        start_command,
+    };
+    Router::new()
+        .route_service("/default", ServeFile::new(&config.default_path))
+        .route("/current", get(current))
+        .route("/default.json", get(default_json))
+        .route("/current.json", get(current_json))
+        .route("/schema", get(schema))
+        .route("/save", post(save))
+        .route("/pending", get(pending))
+        .route("/apply", post(apply))
+        .route("/presets", get(preset_list))
+        .route(
+            "/presets/:name",
+            get(preset_read).put(preset_write).delete(preset_delete),
+        )
+        .route("/presets/:name/from_current", post(preset_from_current))
+        .route("/presets/:name/diff", get(preset_diff))
+        .route("/presets/:name/apply", post(preset_apply))
+        .route("/history", get(history_list))
+        .route("/history/:id", get(history_read))
+        .route("/history/:id/diff", get(history_diff))
+        .route("/history/:id/rollback", post(history_rollback))
+        .route("/files", get(file_list))
+        .route("/files/:file", get(file_read))
+        .route("/files/:file/save", post(file_save))
+        .route("/files/:file/history", get(file_history_list))
+        .route("/files/:file/history/:id", get(file_history_read))
+        .route(
+            "/files/:file/history/:id/rollback",
+            post(file_history_rollback),
+        )
+        .route("/tweaks", get(tweak_list))
+        .route("/tweaks/:name/apply", post(tweak_apply))
+        .with_state(Arc::new(config))
+}
+
+async fn settings_json(path: &std::path::Path) -> AppResult<Json<PalWorldSettings>> {
+    let ini = tokio::fs::read_to_string(path).await?;
+    Ok(Json(PalWorldSettings::from_ini(&ini)?))
+}
+
+async fn default_json(State(c): State<Arc<GameConfig>>) -> AppResult<Json<PalWorldSettings>> {
+    settings_json(&c.default_path).await
+}
+
+async fn schema(State(c): State<Arc<GameConfig>>) -> AppResult<Json<Vec<SettingSchema>>> {
+    let defaults = match read_optional(&c.default_path).await? {
+        Some(ini) => PalWorldSettings::from_ini(&ini)?,
+        None => PalWorldSettings::default(),
+    };
+    Ok(Json(defaults.schema_with_defaults()))
+}
+
+async fn current(State(c): State<Arc<GameConfig>>) -> AppResult<Response> {
+    Ok(match read_optional(&c.current_path).await? {
+        Some(content) => ([(ETAG, etag(content.as_bytes()))], content).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+async fn current_json(State(c): State<Arc<GameConfig>>) -> AppResult<impl IntoResponse> {
+    let ini = tokio::fs::read_to_string(&c.current_path).await?;
+    Ok((
+        [(ETAG, etag(ini.as_bytes()))],
+        Json(PalWorldSettings::from_ini(&ini)?),
+    ))
+}
+
+/// Checks `If-Match` against the current file, or `If-None-Match: *` if it does not exist yet.
+fn check_version(headers: &HeaderMap, current: Option<&str>) -> Result<(), GameConfigError> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    match (current, header(IF_MATCH), header(IF_NONE_MATCH)) {
+        (None, _, Some("*")) => Ok(()),
+        (Some(current), Some(if_match), _) => {
+            let current = etag(current.as_bytes());
+            if if_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == current)
+            {
+                Ok(())
+            } else {
+                Err(GameConfigError::VersionMismatch { current })
+            }
+        }
+        (None, Some(_), _) => Err(GameConfigError::VersionMismatch {
+            current: "none".to_string(),
+        }),
+        (Some(current), None, Some(_)) => Err(GameConfigError::VersionMismatch {
+            current: etag(current.as_bytes()),
+        }),
+        (_, None, _) => Err(GameConfigError::VersionRequired),
+    }
+}
+
+const UNKNOWN_AUTHOR: &str = "unknown";
+
+#[derive(Debug, Deserialize)]
+struct SaveQuery {
+    /// Skip validation, for settings this gateway does not understand correctly.
+    force: Option<bool>,
+    /// Recorded in history along with the replaced version.
+    author: Option<String>,
+}
+
+impl SaveQuery {
+    fn author(&self) -> &str {
+        self.author.as_deref().unwrap_or(UNKNOWN_AUTHOR)
+    }
+}
+
+async fn save(
+    State(c): State<Arc<GameConfig>>,
+    Query(q): Query<SaveQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> AppResult<impl IntoResponse> {
+    if !q.force.unwrap_or(false) {
+        PalWorldSettings::validate_ini(&body)?;
+    }
+    let _guard = c.write_lock.lock().await;
+    let current = read_optional(&c.current_path).await?;
+    check_version(&headers, current.as_deref())?;
+    write_current(&c, current, &body, q.author()).await?;
+    Ok([(ETAG, etag(body.as_bytes()))])
+}
+
+/// Archives the current file, then replaces it. Callers must hold `write_lock`.
+async fn write_current(
+    c: &GameConfig,
+    current: Option<String>,
+    content: &str,
+    author: &str,
+) -> AppResult<()> {
+    write_archived(&c.history, &c.current_path, current, content, author).await
+}
+
+async fn write_archived(
+    history: &History,
+    path: &std::path::Path,
+    current: Option<String>,
+    content: &str,
+    author: &str,
+) -> AppResult<()> {
+    if let Some(current) = current {
+        history.archive(current, author).await?;
+    }
+    write_atomically(path, content).await?;
+    Ok(())
+}
+
+async fn read_version(c: &GameConfig, id: u64) -> AppResult<String> {
+    Ok(c.history
+        .read(id)
+        .await?
+        .ok_or(GameConfigError::VersionNotFound(id))?)
+}
+
+async fn history_list(State(c): State<Arc<GameConfig>>) -> AppResult<Json<Vec<VersionInfo>>> {
+    Ok(Json(c.history.list().await?))
+}
+
+async fn history_read(State(c): State<Arc<GameConfig>>, Path(id): Path<u64>) -> AppResult<String> {
+    read_version(&c, id).await
+}
+
+/// What rolling back to the version would change, per `OptionSettings` key.
+async fn history_diff(
+    State(c): State<Arc<GameConfig>>,
+    Path(id): Path<u64>,
+) -> AppResult<Json<Vec<PropDiff>>> {
+    let version = read_version(&c, id).await?;
+    let current = tokio::fs::read_to_string(&c.current_path).await?;
+    Ok(Json(diff_ini(&current, &version)?))
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthorQuery {
+    author: Option<String>,
+}
+
+async fn history_rollback(
+    State(c): State<Arc<GameConfig>>,
+    Path(id): Path<u64>,
+    Query(q): Query<AuthorQuery>,
+) -> AppResult<()> {
+    let version = read_version(&c, id).await?;
+    let _guard = c.write_lock.lock().await;
+    let current = read_optional(&c.current_path).await?;
+    let author = q.author.as_deref().unwrap_or(UNKNOWN_AUTHOR);
+    write_current(&c, current, &version, author).await
+}
+
+/// Changes on disk that the running server has not loaded yet.
+async fn pending_changes(c: &GameConfig) -> AppResult<Vec<PropDiff>> {
+    let current = read_optional(&c.current_path).await?.unwrap_or_default();
+    Ok(diff_ini(&c.running.content(), &current)?)
+}
+
+async fn pending(State(c): State<Arc<GameConfig>>) -> AppResult<Json<Vec<PropDiff>>> {
+    Ok(Json(pending_changes(&c).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct ApplyRequest {
+    /// Delay before shutting down, so players can finish what they are doing.
+    seconds: Option<usize>,
+    message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ApplyResponse {
+    changes: Vec<PropDiff>,
+    /// Whether the gateway will start the server itself.
+    start_command: bool,
+}
+
+/// Saves the world and shuts the server down, so it picks up pending changes when it starts. They
+/// stay pending until it answers again, see [`RunningSettings`].
+async fn apply(
+    State(c): State<Arc<GameConfig>>,
+    Json(req): Json<ApplyRequest>,
+) -> AppResult<Json<ApplyResponse>> {
+    let changes = pending_changes(&c).await?;
+    if changes.is_empty() {
+        return Ok(Json(ApplyResponse {
+            changes,
+            start_command: false,
+        }));
+    }
+
+    let seconds = req.seconds.unwrap_or(30);
+    let message = req
+        .message
+        .unwrap_or_else(|| "Server_restarting_to_apply_settings".to_string());
+    let mut client = c.client.clone();
+    info!("saving: {}", client.save().await?.trim());
+    info!(
+        "shutting down: {}",
+        client.shutdown(seconds, message).await?.trim()
+    );
+
+    let start_command = c.start_command.clone();
+    tokio::spawn(async move {
+        let limit = Duration::from_secs(seconds as u64 + 60);
+        if !client.wait_for_exit(limit).await {
+            warn!("server still up after {:?}, not starting it", limit);
+            return;
+        }
+        if let Some(command) = start_command {
+            info!("server exited, starting it with `{}`", command);
+            if let Err(err) = pal::start_server(&command) {
+                error!("failed to run start command: {}", err);
+            }
+        }
+    });
+
+    Ok(Json(ApplyResponse {
+        changes,
+        start_command: c.start_command.is_some(),
+    }))
+}
+
+async fn preset_list(State(c): State<Arc<GameConfig>>) -> AppResult<Json<Vec<String>>> {
+    Ok(Json(c.presets.list().await?))
+}
+
+/// The preset as an Unreal struct, the same format accepted by [`preset_write`].
+async fn preset_read(
+    State(c): State<Arc<GameConfig>>,
+    Path(name): Path<String>,
+) -> AppResult<String> {
+    Ok(serialize_struct(&c.presets.read(&name).await?).map_err(GameConfigError::from)?)
+}
+
+async fn preset_write(
+    State(c): State<Arc<GameConfig>>,
+    Path(name): Path<String>,
+    Query(q): Query<SaveQuery>,
+    body: String,
+) -> AppResult<()> {
+    let props = parse_struct(body.trim()).map_err(GameConfigError::from)?;
+    if !q.force.unwrap_or(false) {
+        let errors = PalWorldSettings::validate(&props);
+        if !errors.is_empty() {
+            return Err(GameConfigError::Invalid(errors).into());
+        }
+    }
+    Ok(c.presets.write(&name, &props).await?)
+}
+
+async fn preset_delete(
+    State(c): State<Arc<GameConfig>>,
+    Path(name): Path<String>,
+) -> AppResult<()> {
+    Ok(c.presets.delete(&name).await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct FromCurrentRequest {
+    /// Only take these keys, all of them if absent.
+    keys: Option<Vec<String>>,
+}
+
+async fn preset_from_current(
+    State(c): State<Arc<GameConfig>>,
+    Path(name): Path<String>,
+    req: Option<Json<FromCurrentRequest>>,
+) -> AppResult<()> {
+    let current = read_optional(&c.current_path).await?.unwrap_or_default();
+    let mut props = option_settings_props(&current)?.unwrap_or_default();
+    if let Some(Json(FromCurrentRequest { keys: Some(keys) })) = req {
+        props.retain(|(k, _)| keys.contains(k));
+    }
+    Ok(c.presets.write(&name, &props).await?)
+}
+
+/// The current file with the preset applied, based on the defaults if it has no settings yet.
+async fn with_preset(c: &GameConfig, current: &str, name: &str) -> AppResult<String> {
+    let overlay = c.presets.read(name).await?;
+    let props = match option_settings_props(current)? {
+        Some(props) => props,
+        None => {
+            let default = read_optional(&c.default_path).await?.unwrap_or_default();
+            option_settings_props(&default)?.unwrap_or_default()
+        }
+    };
+    Ok(with_option_settings(
+        current,
+        &apply_overlay(props, &overlay),
+    )?)
+}
+
+/// What applying the preset would change.
+async fn preset_diff(
+    State(c): State<Arc<GameConfig>>,
+    Path(name): Path<String>,
+) -> AppResult<Json<Vec<PropDiff>>> {
+    let current = read_optional(&c.current_path).await?.unwrap_or_default();
+    let applied = with_preset(&c, &current, &name).await?;
+    Ok(Json(diff_ini(&current, &applied)?))
+}
+
+async fn preset_apply(
+    State(c): State<Arc<GameConfig>>,
+    Path(name): Path<String>,
+    Query(q): Query<SaveQuery>,
+) -> AppResult<impl IntoResponse> {
+    let _guard = c.write_lock.lock().await;
+    let current = read_optional(&c.current_path).await?;
+    let applied = with_preset(&c, current.as_deref().unwrap_or_default(), &name).await?;
+    if !q.force.unwrap_or(false) {
+        PalWorldSettings::validate_ini(&applied)?;
+    }
+    write_current(&c, current, &applied, q.author()).await?;
+    Ok([(ETAG, etag(applied.as_bytes()))])
+}
+
+impl GameConfig {
+    fn file_path(&self, file: ConfigFile) -> PathBuf {
+        self.config_dir.join(file.file_name())
+    }
+
+    fn file_history(&self, file: ConfigFile) -> History {
+        History::new(self.history_dir.join(file.name()))
+    }
+}
+
+async fn file_list() -> Json<[ConfigFile; 2]> {
+    Json(ConfigFile::ALL)
+}
+
+async fn file_read(
+    State(c): State<Arc<GameConfig>>,
+    Path(file): Path<ConfigFile>,
+) -> AppResult<Response> {
+    Ok(match read_optional(c.file_path(file)).await? {
+        Some(content) => ([(ETAG, etag(content.as_bytes()))], content).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+async fn file_save(
+    State(c): State<Arc<GameConfig>>,
+    Path(file): Path<ConfigFile>,
+    Query(q): Query<SaveQuery>,
+    headers: HeaderMap,
+    body: String,
+) -> AppResult<impl IntoResponse> {
+    if !q.force.unwrap_or(false) {
+        files::validate(&body)?;
+    }
+    let _guard = c.write_lock.lock().await;
+    let path = c.file_path(file);
+    let current = read_optional(&path).await?;
+    check_version(&headers, current.as_deref())?;
+    write_archived(&c.file_history(file), &path, current, &body, q.author()).await?;
+    Ok([(ETAG, etag(body.as_bytes()))])
+}
+
+async fn file_history_list(
+    State(c): State<Arc<GameConfig>>,
+    Path(file): Path<ConfigFile>,
+) -> AppResult<Json<Vec<VersionInfo>>> {
+    Ok(Json(c.file_history(file).list().await?))
+}
+
+async fn file_history_read(
+    State(c): State<Arc<GameConfig>>,
+    Path((file, id)): Path<(ConfigFile, u64)>,
+) -> AppResult<String> {
+    Ok(c.file_history(file)
+        .read(id)
+        .await?
+        .ok_or(GameConfigError::VersionNotFound(id))?)
+}
+
+async fn file_history_rollback(
+    State(c): State<Arc<GameConfig>>,
+    Path((file, id)): Path<(ConfigFile, u64)>,
+    Query(q): Query<AuthorQuery>,
+) -> AppResult<()> {
+    let history = c.file_history(file);
+    let version = history
+        .read(id)
+        .await?
+        .ok_or(GameConfigError::VersionNotFound(id))?;
+    let _guard = c.write_lock.lock().await;
+    let path = c.file_path(file);
+    let current = read_optional(&path).await?;
+    let author = q.author.as_deref().unwrap_or(UNKNOWN_AUTHOR);
+    write_archived(&history, &path, current, &version, author).await
+}
+
+async fn tweak_list() -> Json<&'static [Tweak]> {
+    Json(TWEAKS)
+}
+
+async fn tweak_apply(
+    State(c): State<Arc<GameConfig>>,
+    Path(name): Path<String>,
+    Query(q): Query<AuthorQuery>,
+) -> AppResult<impl IntoResponse> {
+    let tweak = find_tweak(&name)?;
+    let _guard = c.write_lock.lock().await;
+    let path = c.file_path(tweak.file);
+    let current = read_optional(&path).await?;
+    let applied = apply_tweak(current.as_deref().unwrap_or_default(), tweak)?;
+    let author = q.author.as_deref().unwrap_or(UNKNOWN_AUTHOR);
+    write_archived(
+        &c.file_history(tweak.file),
+        &path,
+        current,
+        &applied,
+        author,
+    )
+    .await?;
+    Ok([(ETAG, etag(applied.as_bytes()))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "[/Script/Pal.PalGameWorldSettings]\n";
+
+    /// What [`check_version`] makes of the headers, as the status a save would get.
+    fn check(
+        current: Option<&str>,
+        if_match: Option<&str>,
+        if_none_match: Option<&str>,
+    ) -> StatusCode {
+        let mut headers = HeaderMap::new();
+        if let Some(tag) = if_match {
+            headers.insert(IF_MATCH, tag.parse().unwrap());
+        }
+        if let Some(tag) = if_none_match {
+            headers.insert(IF_NONE_MATCH, tag.parse().unwrap());
+        }
+        match check_version(&headers, current) {
+            Ok(()) => StatusCode::OK,
+            Err(err) => err.into_response().status(),
+        }
+    }
+
+    #[test]
+    fn if_match() {
+        let tag = etag(CONTENT.as_bytes());
+        let weak = format!("W/{}", tag);
+        let listed = format!("\"0000000000000000\", {}", tag);
+        for if_match in [tag.as_str(), &weak, &listed, "*"] {
+            assert_eq!(
+                check(Some(CONTENT), Some(if_match), None),
+                StatusCode::OK,
+                "{}",
+                if_match
+            );
+        }
+        let other = "\"0000000000000000\"";
+        assert_eq!(
+            check(Some(CONTENT), Some(other), None),
+            StatusCode::CONFLICT
+        );
+        // only `If-Match` counts once the file exists
+        assert_eq!(check(Some(CONTENT), Some(&tag), Some("*")), StatusCode::OK);
+    }
+
+    #[test]
+    fn if_match_without_file() {
+        let tag = etag(CONTENT.as_bytes());
+        assert_eq!(check(None, Some(&tag), None), StatusCode::CONFLICT);
+        assert_eq!(check(None, Some("*"), None), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert_eq!(check(None, None, Some("*")), StatusCode::OK);
+        assert_eq!(check(Some(CONTENT), None, Some("*")), StatusCode::CONFLICT);
+        let tag = etag(CONTENT.as_bytes());
+        assert_eq!(check(Some(CONTENT), None, Some(&tag)), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn version_required() {
+        assert_eq!(
+            check(Some(CONTENT), None, None),
+            StatusCode::PRECONDITION_REQUIRED
+        );
+        assert_eq!(check(None, None, None), StatusCode::PRECONDITION_REQUIRED);
+        // only `*` creates the file
+        let tag = etag(CONTENT.as_bytes());
+        assert_eq!(
+            check(None, None, Some(&tag)),
+            StatusCode::PRECONDITION_REQUIRED
+        );
+    }
+}
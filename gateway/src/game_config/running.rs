@@ -0,0 +1,107 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    pal::{DialTarget, PalServerClient, RestartWatcher},
+    paths::Paths,
+};
+
+use super::settings::RconSettings;
+
+/// Which parts of the RCON address follow `PalWorldSettings.ini`, i.e. were not pinned in the
+/// environment.
+#[derive(Debug, Clone, Default)]
+pub struct RconSync {
+    /// Host to dial at `RCONPort`.
+    pub host: Option<String>,
+    /// Whether to log in with `AdminPassword`.
+    pub password: bool,
+}
+
+/// The settings file the running server was started with, as far as the gateway knows. Updated
+/// when the RCON client finds the server restarted, however that happened.
+#[derive(Debug, Clone)]
+pub struct RunningSettings(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    settings_file: PathBuf,
+    content: Mutex<String>,
+    /// Read when the connection was lost, the server runs it once it answers again.
+    next: Mutex<Option<String>>,
+    rcon_sync: RconSync,
+}
+
+impl RunningSettings {
+    /// The gateway usually starts along with the server, so it is taken to run the current file.
+    /// `client` follows its RCON settings from now on.
+    pub async fn new(paths: &Paths, client: &PalServerClient, rcon_sync: RconSync) -> Self {
+        let settings_file = paths.settings_file();
+        let content = std::fs::read_to_string(&settings_file).unwrap_or_default();
+        let running = RunningSettings(Arc::new(Inner {
+            settings_file,
+            content: Mutex::new(content),
+            next: Mutex::new(None),
+            rcon_sync,
+        }));
+        client.watch_restarts(running.0.clone()).await;
+        running
+    }
+
+    pub fn content(&self) -> String {
+        self.0.content.lock().unwrap().clone()
+    }
+}
+
+impl RestartWatcher for Inner {
+    fn next_target(&self, current: &DialTarget) -> DialTarget {
+        let next = match std::fs::read_to_string(&self.settings_file) {
+            Ok(next) => next,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                warn!(
+                    "failed to read the settings the server restarts with: {}",
+                    err
+                );
+                return current.clone();
+            }
+        };
+        let rcon = rcon_settings(&next);
+        *self.next.lock().unwrap() = Some(next);
+        DialTarget {
+            addr: match &self.rcon_sync.host {
+                Some(host) => format!("{}:{}", host, rcon.port),
+                None => current.addr.clone(),
+            },
+            password: match self.rcon_sync.password {
+                true => rcon.password,
+                false => current.password.clone(),
+            },
+        }
+    }
+
+    fn restarted(&self) {
+        if let Some(next) = self.next.lock().unwrap().take() {
+            let mut content = self.content.lock().unwrap();
+            if *content != next {
+                info!("the server restarted with changed settings");
+                *content = next;
+            }
+        }
+    }
+}
+
+/// [`RconSettings::from_ini`], with the server's defaults if `OptionSettings` does not parse.
+pub fn rcon_settings(ini: &str) -> RconSettings {
+    RconSettings::from_ini(ini).unwrap_or_else(|err| {
+        warn!(
+            "failed to read the RCON settings, using the defaults: {}",
+            err
+        );
+        RconSettings::default()
+    })
+}
@@ -0,0 +1,680 @@
+use ini::{EscapePolicy, Ini, LineSeparator, ParseOption, WriteOption};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    unreal_struct::{parse_struct, serialize_struct, Value},
+    GameConfigError,
+};
+
+pub const SECTION: &str = "/Script/Pal.PalGameWorldSettings";
+pub const OPTION_SETTINGS: &str = "OptionSettings";
+
+/// A type that can be stored in a single `OptionSettings` entry.
+pub trait OptionValue: Sized {
+    /// Type name used in the schema.
+    const KIND: &'static str;
+    /// Accepted values if there is a fixed set of them.
+    const VARIANTS: Option<&'static [&'static str]> = None;
+
+    fn from_value(value: &Value) -> Option<Self>;
+    fn to_value(&self) -> Value;
+    /// Describes accepted values, for telling the user why [`OptionValue::from_value`] failed.
+    fn expected() -> String;
+}
+
+impl OptionValue for f32 {
+    const KIND: &'static str = "float";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Primitive(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+    fn to_value(&self) -> Value {
+        // Unreal always writes six decimal places
+        Value::Primitive(format!("{:.6}", self))
+    }
+    fn expected() -> String {
+        "a number".to_string()
+    }
+}
+
+impl OptionValue for i32 {
+    const KIND: &'static str = "integer";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Primitive(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+    fn to_value(&self) -> Value {
+        Value::Primitive(self.to_string())
+    }
+    fn expected() -> String {
+        "an integer".to_string()
+    }
+}
+
+impl OptionValue for bool {
+    const KIND: &'static str = "boolean";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Primitive(s) if s.eq_ignore_ascii_case("true") => Some(true),
+            Value::Primitive(s) if s.eq_ignore_ascii_case("false") => Some(false),
+            _ => None,
+        }
+    }
+    fn to_value(&self) -> Value {
+        Value::Primitive(if *self { "True" } else { "False" }.to_string())
+    }
+    fn expected() -> String {
+        "True or False".to_string()
+    }
+}
+
+impl OptionValue for String {
+    const KIND: &'static str = "string";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(s) | Value::Primitive(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+    fn to_value(&self) -> Value {
+        Value::String(self.clone())
+    }
+    fn expected() -> String {
+        "a quoted string".to_string()
+    }
+}
+
+macro_rules! unreal_enum {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl OptionValue for $name {
+            const KIND: &'static str = "enum";
+            const VARIANTS: Option<&'static [&'static str]> = Some(&[$(stringify!($variant),)*]);
+
+            fn from_value(value: &Value) -> Option<Self> {
+                match value {
+                    $(Value::Primitive(s) if s == stringify!($variant) => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+            fn to_value(&self) -> Value {
+                Value::Primitive(match self {
+                    $(Self::$variant => stringify!($variant),)*
+                }.to_string())
+            }
+            fn expected() -> String {
+                format!("one of {}", Self::VARIANTS.unwrap().join(", "))
+            }
+        }
+    };
+}
+
+unreal_enum! {
+    Difficulty {
+        None,
+        Normal,
+        Difficult,
+    }
+}
+
+unreal_enum! {
+    /// What a player drops on death.
+    DeathPenalty {
+        None,
+        Item,
+        ItemAndEquipment,
+        All,
+    }
+}
+
+/// A problem with a single `OptionSettings` entry.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub key: String,
+    pub value: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(key: &str, value: &Value, message: String) -> Self {
+        FieldError {
+            key: key.to_string(),
+            value: value.to_string(),
+            message,
+        }
+    }
+}
+
+/// Metadata of a known `OptionSettings` key, for building forms.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingSchema {
+    pub key: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Taken from `DefaultPalWorldSettings.ini`, `None` if it is not available.
+    pub default: Option<serde_json::Value>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub values: Option<&'static [&'static str]>,
+    pub unit: Option<&'static str>,
+    pub description: String,
+}
+
+macro_rules! pal_world_settings {
+    ($(
+        $(#[doc = $doc:literal])*
+        $(#[unit = $unit:literal])?
+        $key:literal => $field:ident: $ty:ty $([$min:literal, $max:literal])?,
+    )*) => {
+        /// Typed view of `OptionSettings` in `PalWorldSettings.ini`.
+        ///
+        /// Keys missing from the file are `None`, so the server falls back to its defaults.
+        #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+        pub struct PalWorldSettings {
+            $(
+                $(#[doc = $doc])*
+                #[serde(rename = $key, default, skip_serializing_if = "Option::is_none")]
+                pub $field: Option<$ty>,
+            )*
+            /// Keys unknown to this version of the gateway, kept in their original order.
+            #[serde(default)]
+            pub unknown: Vec<(String, Value)>,
+        }
+
+        impl PalWorldSettings {
+            pub const KEYS: &'static [&'static str] = &[$($key,)*];
+
+            pub fn from_props(props: Vec<(String, Value)>) -> Result<Self, GameConfigError> {
+                let mut settings = Self::default();
+                for (key, value) in props {
+                    match key.as_str() {
+                        $(
+                            $key => {
+                                settings.$field = Some(OptionValue::from_value(&value).ok_or_else(
+                                    || GameConfigError::InvalidValue { key, value: value.to_string() },
+                                )?);
+                            }
+                        )*
+                        _ => settings.unknown.push((key, value)),
+                    }
+                }
+                Ok(settings)
+            }
+
+            /// Describes every known key, without defaults.
+            pub fn schema() -> Vec<SettingSchema> {
+                vec![$(
+                    SettingSchema {
+                        key: $key,
+                        kind: <$ty as OptionValue>::KIND,
+                        default: None,
+                        min: None $(.or(Some($min as f64)))?,
+                        max: None $(.or(Some($max as f64)))?,
+                        values: <$ty as OptionValue>::VARIANTS,
+                        unit: None $(.or(Some($unit)))?,
+                        description: {
+                            let docs: &[&str] = &[$($doc),*];
+                            docs.iter().map(|d| d.trim()).collect::<Vec<_>>().join(" ")
+                        },
+                    },
+                )*]
+            }
+
+            /// Checks every known key for its type and range, collecting all problems.
+            pub fn validate(props: &[(String, Value)]) -> Vec<FieldError> {
+                let mut errors = Vec::new();
+                for (key, value) in props {
+                    match key.as_str() {
+                        $(
+                            $key => match <$ty as OptionValue>::from_value(value) {
+                                None => errors.push(FieldError::new(
+                                    key,
+                                    value,
+                                    format!("expected {}", <$ty as OptionValue>::expected()),
+                                )),
+                                #[allow(unused_variables)]
+                                Some(v) => {
+                                    $(
+                                        if !($min..=$max).contains(&v) {
+                                            errors.push(FieldError::new(
+                                                key,
+                                                value,
+                                                format!("must be between {} and {}", $min, $max),
+                                            ));
+                                        }
+                                    )?
+                                }
+                            },
+                        )*
+                        _ => {}
+                    }
+                }
+                errors
+            }
+
+            pub fn to_props(&self) -> Vec<(String, Value)> {
+                let mut props = Vec::new();
+                $(
+                    if let Some(v) = &self.$field {
+                        props.push(($key.to_string(), v.to_value()));
+                    }
+                )*
+                props.extend(self.unknown.iter().cloned());
+                props
+            }
+        }
+    };
+}
+
+pal_world_settings! {
+    /// Difficulty preset
+    "Difficulty" => difficulty: Difficulty,
+    /// How fast time passes during the day
+    #[unit = "x"]
+    "DayTimeSpeedRate" => day_time_speed_rate: f32 [0.1, 20.0],
+    /// How fast time passes at night
+    #[unit = "x"]
+    "NightTimeSpeedRate" => night_time_speed_rate: f32 [0.1, 20.0],
+    /// Multiplier for experience gained
+    #[unit = "x"]
+    "ExpRate" => exp_rate: f32 [0.1, 20.0],
+    /// Multiplier for the chance of capturing a Pal
+    #[unit = "x"]
+    "PalCaptureRate" => pal_capture_rate: f32 [0.1, 20.0],
+    /// Multiplier for the number of Pals that appear
+    #[unit = "x"]
+    "PalSpawnNumRate" => pal_spawn_num_rate: f32 [0.1, 20.0],
+    /// Multiplier for damage dealt by Pals
+    #[unit = "x"]
+    "PalDamageRateAttack" => pal_damage_rate_attack: f32 [0.1, 20.0],
+    /// Multiplier for damage taken by Pals
+    #[unit = "x"]
+    "PalDamageRateDefense" => pal_damage_rate_defense: f32 [0.1, 20.0],
+    /// Multiplier for damage dealt by players
+    #[unit = "x"]
+    "PlayerDamageRateAttack" => player_damage_rate_attack: f32 [0.1, 20.0],
+    /// Multiplier for damage taken by players
+    #[unit = "x"]
+    "PlayerDamageRateDefense" => player_damage_rate_defense: f32 [0.1, 20.0],
+    /// How fast players get hungry
+    #[unit = "x"]
+    "PlayerStomachDecreaceRate" => player_stomach_decreace_rate: f32 [0.1, 20.0],
+    /// How fast players use up stamina
+    #[unit = "x"]
+    "PlayerStaminaDecreaceRate" => player_stamina_decreace_rate: f32 [0.1, 20.0],
+    /// How fast players regenerate HP
+    #[unit = "x"]
+    "PlayerAutoHPRegeneRate" => player_auto_hp_regene_rate: f32 [0.1, 20.0],
+    /// How fast players regenerate HP while sleeping
+    #[unit = "x"]
+    "PlayerAutoHpRegeneRateInSleep" => player_auto_hp_regene_rate_in_sleep: f32 [0.1, 20.0],
+    /// How fast Pals get hungry
+    #[unit = "x"]
+    "PalStomachDecreaceRate" => pal_stomach_decreace_rate: f32 [0.1, 20.0],
+    /// How fast Pals use up stamina
+    #[unit = "x"]
+    "PalStaminaDecreaceRate" => pal_stamina_decreace_rate: f32 [0.1, 20.0],
+    /// How fast Pals regenerate HP
+    #[unit = "x"]
+    "PalAutoHPRegeneRate" => pal_auto_hp_regene_rate: f32 [0.1, 20.0],
+    /// How fast Pals regenerate HP while resting in the Palbox
+    #[unit = "x"]
+    "PalAutoHpRegeneRateInSleep" => pal_auto_hp_regene_rate_in_sleep: f32 [0.1, 20.0],
+    /// Multiplier for damage dealt to structures
+    #[unit = "x"]
+    "BuildObjectDamageRate" => build_object_damage_rate: f32 [0.1, 20.0],
+    /// How fast structures deteriorate, 0 turns it off
+    #[unit = "x"]
+    "BuildObjectDeteriorationDamageRate" => build_object_deterioration_damage_rate: f32 [0.0, 20.0],
+    /// Multiplier for items gathered from trees, rocks and the like
+    #[unit = "x"]
+    "CollectionDropRate" => collection_drop_rate: f32 [0.1, 20.0],
+    /// Multiplier for HP of trees, rocks and the like
+    #[unit = "x"]
+    "CollectionObjectHpRate" => collection_object_hp_rate: f32 [0.1, 20.0],
+    /// Respawn interval of trees, rocks and the like, lower is faster
+    #[unit = "x"]
+    "CollectionObjectRespawnSpeedRate" => collection_object_respawn_speed_rate: f32 [0.1, 20.0],
+    /// Multiplier for items dropped by defeated enemies
+    #[unit = "x"]
+    "EnemyDropItemRate" => enemy_drop_item_rate: f32 [0.1, 20.0],
+    /// What players drop when they die
+    "DeathPenalty" => death_penalty: DeathPenalty,
+    /// Allow players to damage each other
+    "bEnablePlayerToPlayerDamage" => enable_player_to_player_damage: bool,
+    /// Allow damage between members of the same guild
+    "bEnableFriendlyFire" => enable_friendly_fire: bool,
+    /// Enable raids on bases
+    "bEnableInvaderEnemy" => enable_invader_enemy: bool,
+    /// Let Pals drop dung
+    "bActiveUNKO" => active_unko: bool,
+    /// Enable aim assist for controllers
+    "bEnableAimAssistPad" => enable_aim_assist_pad: bool,
+    /// Enable aim assist for keyboard and mouse
+    "bEnableAimAssistKeyboard" => enable_aim_assist_keyboard: bool,
+    /// Maximum number of dropped items in the world
+    "DropItemMaxNum" => drop_item_max_num: i32 [0, 10000],
+    /// Maximum number of dropped dung in the world
+    "DropItemMaxNum_UNKO" => drop_item_max_num_unko: i32 [0, 10000],
+    /// Maximum number of bases in the world
+    "BaseCampMaxNum" => base_camp_max_num: i32 [1, 500],
+    /// Maximum number of Pals working at a base
+    "BaseCampWorkerMaxNum" => base_camp_worker_max_num: i32 [1, 50],
+    /// How long dropped items stay before they disappear
+    #[unit = "hours"]
+    "DropItemAliveMaxHours" => drop_item_alive_max_hours: f32 [0.0, 240.0],
+    /// Disband guilds whose players have all been offline for a while
+    "bAutoResetGuildNoOnlinePlayers" => auto_reset_guild_no_online_players: bool,
+    /// How long all players of a guild must be offline before it is disbanded
+    #[unit = "hours"]
+    "AutoResetGuildTimeNoOnlinePlayers" => auto_reset_guild_time_no_online_players: f32 [0.0, 10000.0],
+    /// Maximum number of players in a guild
+    "GuildPlayerMaxNum" => guild_player_max_num: i32 [1, 100],
+    /// Time for a huge egg to hatch, smaller eggs take less
+    #[unit = "hours"]
+    "PalEggDefaultHatchingTime" => pal_egg_default_hatching_time: f32 [0.0, 240.0],
+    /// Multiplier for how fast Pals work
+    #[unit = "x"]
+    "WorkSpeedRate" => work_speed_rate: f32 [0.1, 20.0],
+    /// Multiplayer mode, has no effect on dedicated servers
+    "bIsMultiplay" => is_multiplay: bool,
+    /// Enable PvP
+    "bIsPvP" => is_pvp: bool,
+    /// Allow picking up what players of other guilds dropped on death
+    "bCanPickupOtherGuildDeathPenaltyDrop" => can_pickup_other_guild_death_penalty_drop: bool,
+    /// Penalize players who have not logged in for a while
+    "bEnableNonLoginPenalty" => enable_non_login_penalty: bool,
+    /// Enable fast travel
+    "bEnableFastTravel" => enable_fast_travel: bool,
+    /// Let new players pick their start location on the map
+    "bIsStartLocationSelectByMap" => is_start_location_select_by_map: bool,
+    /// Keep players' characters in the world after they log out
+    "bExistPlayerAfterLogout" => exist_player_after_logout: bool,
+    /// Allow defending against players of other guilds
+    "bEnableDefenseOtherGuildPlayer" => enable_defense_other_guild_player: bool,
+    /// Maximum number of players in a co-op session
+    "CoopPlayerMaxNum" => coop_player_max_num: i32 [1, 32],
+    /// Maximum number of players on the server
+    "ServerPlayerMaxNum" => server_player_max_num: i32 [1, 32],
+    /// Name shown in the server list
+    "ServerName" => server_name: String,
+    /// Description shown in the server list
+    "ServerDescription" => server_description: String,
+    /// Password for admin commands and RCON
+    "AdminPassword" => admin_password: String,
+    /// Password needed to join, empty for none
+    "ServerPassword" => server_password: String,
+    /// Port announced to the community server list, not the one the server listens on
+    "PublicPort" => public_port: i32 [1, 65535],
+    /// IP announced to the community server list, empty to detect it
+    "PublicIP" => public_ip: String,
+    /// Enable RCON, which this dashboard needs
+    "RCONEnabled" => rcon_enabled: bool,
+    /// Port RCON listens on
+    "RCONPort" => rcon_port: i32 [1, 65535],
+    /// Region shown in the server list
+    "Region" => region: String,
+    /// Authenticate players with their platform
+    "bUseAuth" => use_auth: bool,
+    /// Where the server fetches the ban list from
+    "BanListURL" => ban_list_url: String,
+}
+
+pub(super) fn load_ini(ini: &str) -> Result<Ini, GameConfigError> {
+    // values are Unreal structs with their own quoting and escaping
+    Ok(Ini::load_from_str_opt(
+        ini,
+        ParseOption {
+            enabled_quote: false,
+            enabled_escape: false,
+        },
+    )?)
+}
+
+/// Extracts the raw `OptionSettings` struct from an INI document, if there is one.
+pub fn option_settings_props(raw: &str) -> Result<Option<Vec<(String, Value)>>, GameConfigError> {
+    let ini = load_ini(raw)?;
+    let Some(v) = ini
+        .section(Some(SECTION))
+        .and_then(|sec| sec.get(OPTION_SETTINGS))
+    else {
+        return Ok(None);
+    };
+    parse_struct(v).map(Some).map_err(|mut err| {
+        // report the position in the whole document instead of the value alone
+        if let Some((i, line)) = raw
+            .lines()
+            .enumerate()
+            .find(|(_, l)| l.trim_start().starts_with(OPTION_SETTINGS))
+        {
+            err.line = i + 1;
+            err.column += line.find(v).unwrap_or_default();
+        }
+        err.into()
+    })
+}
+
+impl PalWorldSettings {
+    /// Parses the content of `PalWorldSettings.ini` (or `DefaultPalWorldSettings.ini`).
+    ///
+    /// An empty file is valid, the server generates one like this on first start.
+    pub fn from_ini(ini: &str) -> Result<Self, GameConfigError> {
+        match option_settings_props(ini)? {
+            Some(props) => Self::from_props(props),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parses an INI document and validates every known key, see [`PalWorldSettings::validate`].
+    pub fn validate_ini(ini: &str) -> Result<(), GameConfigError> {
+        let errors = option_settings_props(ini)?
+            .map(|props| Self::validate(&props))
+            .unwrap_or_default();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(GameConfigError::Invalid(errors))
+        }
+    }
+
+    /// [`PalWorldSettings::schema`] with defaults taken from these settings.
+    pub fn schema_with_defaults(&self) -> Vec<SettingSchema> {
+        let defaults = serde_json::to_value(self).unwrap_or_default();
+        Self::schema()
+            .into_iter()
+            .map(|schema| SettingSchema {
+                default: defaults.get(schema.key).cloned(),
+                ..schema
+            })
+            .collect()
+    }
+
+    /// Writes these settings into an INI document, see [`with_option_settings`].
+    pub fn to_ini(&self, ini: &str) -> Result<String, GameConfigError> {
+        with_option_settings(ini, &self.to_props())
+    }
+}
+
+pub const DEFAULT_RCON_PORT: u16 = 25575;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RconSettings {
+    pub enabled: bool,
+    pub port: u16,
+    /// `AdminPassword`, `None` if it is empty.
+    pub password: Option<String>,
+}
+
+/// The server's defaults.
+impl Default for RconSettings {
+    fn default() -> Self {
+        RconSettings {
+            enabled: false,
+            port: DEFAULT_RCON_PORT,
+            password: None,
+        }
+    }
+}
+
+impl RconSettings {
+    /// How the server accepts RCON connections, with the server's defaults for missing or
+    /// invalid keys. Only these keys are read, so an invalid value elsewhere does not hide them.
+    pub fn from_ini(ini: &str) -> Result<RconSettings, GameConfigError> {
+        let props = option_settings_props(ini)?.unwrap_or_default();
+        let get = |key: &str| props.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+        Ok(RconSettings {
+            enabled: get("RCONEnabled")
+                .and_then(bool::from_value)
+                .unwrap_or(false),
+            port: get("RCONPort")
+                .and_then(i32::from_value)
+                .and_then(|port| u16::try_from(port).ok())
+                .filter(|port| *port != 0)
+                .unwrap_or(DEFAULT_RCON_PORT),
+            password: get("AdminPassword")
+                .and_then(String::from_value)
+                .filter(|p| !p.is_empty()),
+        })
+    }
+}
+
+/// Replaces (or adds) `OptionSettings` in an INI document, leaving other keys untouched.
+pub fn with_option_settings(
+    ini: &str,
+    props: &[(String, Value)],
+) -> Result<String, GameConfigError> {
+    let mut ini = load_ini(ini)?;
+    ini.with_section(Some(SECTION))
+        .set(OPTION_SETTINGS, serialize_struct(props)?);
+    Ok(write_ini(&ini))
+}
+
+/// Writes values verbatim, the counterpart of [`load_ini`].
+pub(super) fn write_ini(ini: &Ini) -> String {
+    let mut buf = Vec::new();
+    ini.write_to_opt(
+        &mut buf,
+        WriteOption {
+            escape_policy: EscapePolicy::Nothing,
+            line_separator: LineSeparator::SystemDefault,
+            kv_separator: "=",
+        },
+    )
+    .expect("writing to a Vec never fails");
+    String::from_utf8(buf).expect("INI written from strings is UTF-8")
+}
+
+/// How a single `OptionSettings` key differs between two versions, `None` if absent.
+#[derive(Debug, Clone, Serialize)]
+pub struct PropDiff {
+    pub key: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// Changed keys from `old` to `new`, in the order of `new` followed by removed keys.
+pub fn diff_props(old: &[(String, Value)], new: &[(String, Value)]) -> Vec<PropDiff> {
+    let find = |props: &[(String, Value)], key: &str| {
+        props.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+    };
+    let changed = new.iter().filter_map(|(key, value)| {
+        let old = find(old, key);
+        (old.as_ref() != Some(value)).then(|| PropDiff {
+            key: key.clone(),
+            old,
+            new: Some(value.clone()),
+        })
+    });
+    let removed = old
+        .iter()
+        .filter(|(key, _)| find(new, key).is_none())
+        .map(|(key, value)| PropDiff {
+            key: key.clone(),
+            old: Some(value.clone()),
+            new: None,
+        });
+    changed.chain(removed).collect()
+}
+
+/// [`diff_props`] on the `OptionSettings` of two INI documents.
+pub fn diff_ini(old: &str, new: &str) -> Result<Vec<PropDiff>, GameConfigError> {
+    Ok(diff_props(
+        &option_settings_props(old)?.unwrap_or_default(),
+        &option_settings_props(new)?.unwrap_or_default(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn struct_error(ini: &str) -> crate::game_config::unreal_struct::ParseError {
+        match option_settings_props(ini) {
+            Err(GameConfigError::StructError(err)) => err,
+            res => panic!("expected a struct error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn struct_error_position_in_document() {
+        let header = format!("; comment\n[{}]\n", SECTION);
+        let err = struct_error(&format!("{}OptionSettings=(Difficulty=None,=1)\n", header));
+        assert_eq!(
+            (err.line, err.column, err.expected),
+            (3, 33, vec!["identifier".to_string()])
+        );
+        let err = struct_error(&format!(
+            "{}OptionSettings = (Difficulty=None,=1)\n",
+            header
+        ));
+        assert_eq!((err.line, err.column), (3, 35));
+        let err = struct_error(&format!("{}OptionSettings=Difficulty\n", header));
+        assert_eq!(
+            (err.line, err.column, err.expected),
+            (3, 16, vec!["struct".to_string()])
+        );
+    }
+
+    #[test]
+    fn rcon_settings_despite_invalid_keys() {
+        let ini = format!(
+            "[{}]\n{}=(Difficulty=Casual,RCONEnabled=True,RCONPort=25576,AdminPassword=\"secret\")\n",
+            SECTION, OPTION_SETTINGS
+        );
+        assert!(PalWorldSettings::from_ini(&ini).is_err());
+        assert_eq!(
+            RconSettings::from_ini(&ini).unwrap(),
+            RconSettings {
+                enabled: true,
+                port: 25576,
+                password: Some("secret".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn rcon_settings_defaults() {
+        let ini = format!(
+            "[{}]\n{}=(RCONEnabled=Maybe,RCONPort=70000,AdminPassword=\"\")\n",
+            SECTION, OPTION_SETTINGS
+        );
+        assert_eq!(
+            RconSettings::from_ini(&ini).unwrap(),
+            RconSettings::default()
+        );
+        assert_eq!(RconSettings::from_ini("").unwrap(), RconSettings::default());
+    }
+}
@@ -1,41 +1,408 @@
-use pest::Parser;
+use std::fmt;
+
+use pest::{
+    error::{ErrorVariant, LineColLocation},
+    iterators::Pair,
+    Parser,
+};
 use pest_derive::Parser;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 #[derive(Parser)]
 #[grammar = "game_config/unreal_struct.pest"]
 pub struct UnrealSturctParser;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Value {
+    /// Anything unquoted, including numbers, booleans, enum names and empty values.
     Primitive(String),
     String(String),
+    Struct(Vec<(String, Value)>),
+    Array(Vec<Value>),
+}
+
+/// Where and why a struct failed to parse, positions are 1-based.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[error("{line}:{column}: expected {}", expected.join(" or "))]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub expected: Vec<String>,
+}
+
+/// A value [`serialize_struct`] cannot write so that [`parse_struct`] reads it back the same.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum SerializeError {
+    #[error("`{0}` is not a property name")]
+    InvalidName(String),
+    #[error("`{0}` contains `\"`, `,`, `(` or `)`, quote it as a string instead")]
+    InvalidPrimitive(String),
+    #[error("an empty struct reads back as an empty array")]
+    EmptyStruct,
+    #[error("an array of a single empty value reads back as an empty array")]
+    SingleEmptyElement,
+    #[error("array `{0}` reads back as a struct")]
+    AmbiguousArray(String),
+}
+
+impl From<pest::error::Error<Rule>> for ParseError {
+    fn from(err: pest::error::Error<Rule>) -> Self {
+        let (line, column) = match err.line_col {
+            LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+        };
+        let expected = match err.variant {
+            ErrorVariant::ParsingError { positives, .. } => {
+                positives.iter().map(|r| format!("{:?}", r)).collect()
+            }
+            ErrorVariant::CustomError { message } => vec![message],
+        };
+        ParseError {
+            line,
+            column,
+            expected,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Primitive(s) => write!(f, "{}", s),
+            Value::String(s) => write!(f, "\"{}\"", escape(s)),
+            Value::Struct(props) => write!(f, "{}", write_struct(props)),
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                write!(f, "({})", values.join(","))
+            }
+        }
+    }
+}
+
+impl Value {
+    /// Whether [`parse_struct`] reads the written value back the same, see [`SerializeError`].
+    fn check(&self) -> Result<(), SerializeError> {
+        match self {
+            Value::Primitive(s) if s.contains(['"', ',', '(', ')']) => {
+                Err(SerializeError::InvalidPrimitive(s.clone()))
+            }
+            Value::Primitive(_) | Value::String(_) => Ok(()),
+            Value::Struct(props) => check_props(props),
+            Value::Array(values) => {
+                match values.as_slice() {
+                    [Value::Primitive(s)] if s.is_empty() => {
+                        return Err(SerializeError::SingleEmptyElement)
+                    }
+                    // `(A=1,B=2)` is tried as a struct first
+                    [_, ..] if values.iter().all(Value::looks_like_prop) => {
+                        return Err(SerializeError::AmbiguousArray(self.to_string()))
+                    }
+                    _ => {}
+                }
+                values.iter().try_for_each(Value::check)
+            }
+        }
+    }
+
+    /// A primitive like `Name=...`, which the `prop` rule matches too.
+    fn looks_like_prop(&self) -> bool {
+        match self {
+            Value::Primitive(s) => s
+                .split_once('=')
+                .is_some_and(|(name, _)| is_identifier(name)),
+            _ => false,
+        }
+    }
 }
 
-// pub fn is_parentheses_surrounded(s: &str) -> bool {
-//     s.starts_with('(') && s.ends_with(')')
-// }
+/// Matches the `identifier` rule.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn check_props(props: &[(String, Value)]) -> Result<(), SerializeError> {
+    if props.is_empty() {
+        return Err(SerializeError::EmptyStruct);
+    }
+    for (name, value) in props {
+        if !is_identifier(name) {
+            return Err(SerializeError::InvalidName(name.clone()));
+        }
+        value.check()?;
+    }
+    Ok(())
+}
+
+/// Escapes a string the way the `char` rule in `unreal_struct.pest` reads it back.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
 
-pub fn parse_struct(s: &str) -> Vec<(String, Value)> {
-    // assert!(is_parentheses_surrounded(s));
-    assert!(s.starts_with('(') && s.ends_with(')'));
-    let s = UnrealSturctParser::parse(Rule::r#struct, s)
-        .unwrap()
+/// Reverses [`escape`]. The input is expected to have passed the `inner` rule already.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => unescaped.push('\u{8}'),
+            Some('f') => unescaped.push('\u{c}'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let c = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                unescaped.push(c);
+            }
+            Some(c) => unescaped.push(c), // '"', '\\' and '/'
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+pub fn parse_struct(s: &str) -> Result<Vec<(String, Value)>, ParseError> {
+    let s = UnrealSturctParser::parse(Rule::root, s)?
         .next()
-        .unwrap();
-    let mut props: Vec<(String, Value)> = Vec::new();
-    for prop in s.into_inner().filter(|p| p.as_rule() == Rule::prop) {
-        let mut inner = prop.into_inner();
-        let name = inner.next().unwrap().as_str();
-        let value = inner.next().unwrap();
-
-        props.push((
-            name.to_string(),
-            match value.as_rule() {
-                Rule::primitive => Value::Primitive(value.as_str().to_string()),
-                Rule::string => Value::String(value.as_str().trim_matches('"').to_string()),
-                _ => unreachable!(),
-            },
-        ));
-    }
-    props
+        .expect("root always contains a struct");
+    Ok(struct_props(s))
+}
+
+fn struct_props(pair: Pair<Rule>) -> Vec<(String, Value)> {
+    pair.into_inner()
+        .map(|prop| {
+            let mut inner = prop.into_inner();
+            let name = inner.next().unwrap().as_str();
+            let value = inner.next().unwrap();
+            (name.to_string(), value_of(value))
+        })
+        .collect()
+}
+
+fn value_of(pair: Pair<Rule>) -> Value {
+    match pair.as_rule() {
+        Rule::primitive => Value::Primitive(pair.as_str().to_string()),
+        Rule::string => Value::String(unescape(pair.into_inner().next().unwrap().as_str())),
+        Rule::r#struct => Value::Struct(struct_props(pair)),
+        Rule::array => Value::Array(pair.into_inner().map(value_of).collect()),
+        _ => unreachable!(),
+    }
+}
+
+/// Writes properties back as `(Key=Value,...)`, keeping their order.
+///
+/// Fails rather than writing something [`parse_struct`] would read differently, e.g. a primitive
+/// containing `,`.
+pub fn serialize_struct(props: &[(String, Value)]) -> Result<String, SerializeError> {
+    check_props(props)?;
+    Ok(write_struct(props))
+}
+
+fn write_struct(props: &[(String, Value)]) -> String {
+    let props: Vec<String> = props
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    format!("({})", props.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn props(pairs: &[(&str, Value)]) -> Vec<(String, Value)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn primitive(s: &str) -> Value {
+        Value::Primitive(s.to_string())
+    }
+
+    fn roundtrip(props: &[(String, Value)]) -> Vec<(String, Value)> {
+        parse_struct(&serialize_struct(props).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn nested() {
+        let props = props(&[
+            ("Difficulty", primitive("None")),
+            ("ExpRate", primitive("1.000000")),
+            (
+                "ServerName",
+                Value::String("Default Palworld Server".to_string()),
+            ),
+            ("Region", primitive("")),
+            (
+                "Inner",
+                Value::Struct(props(&[
+                    ("A", Value::Array(vec![primitive("1"), primitive("2")])),
+                    ("B", Value::Array(vec![])),
+                    ("C", Value::Array(vec![Value::Array(vec![])])),
+                ])),
+            ),
+            (
+                "Structs",
+                Value::Array(vec![Value::Struct(props(&[("X", primitive("1"))]))]),
+            ),
+        ]);
+        assert_eq!(roundtrip(&props), props);
+    }
+
+    #[test]
+    fn empty_values() {
+        let props = props(&[
+            ("A", primitive("")),
+            ("B", Value::String(String::new())),
+            ("C", Value::Array(vec![primitive(""), primitive("")])),
+        ]);
+        assert_eq!(serialize_struct(&props).unwrap(), r#"(A=,B="",C=(,))"#);
+        assert_eq!(roundtrip(&props), props);
+    }
+
+    #[test]
+    fn escapes() {
+        let s = "quote \" backslash \\ newline \n tab \t bell \u{7} emoji 🐑";
+        let props = props(&[("S", Value::String(s.to_string()))]);
+        assert_eq!(roundtrip(&props), props);
+        assert_eq!(
+            parse_struct(r#"(S="\/A")"#).unwrap(),
+            [("S".to_string(), Value::String("/A".to_string()))]
+        );
+    }
+
+    #[test]
+    fn parse_error_position() {
+        assert_eq!(
+            parse_struct("(A=1,B=2,\"C\"=3)").unwrap_err(),
+            ParseError {
+                line: 1,
+                column: 10,
+                expected: vec!["identifier".to_string()],
+            }
+        );
+        let err = parse_struct("(A=1)B").unwrap_err();
+        assert_eq!(
+            (err.line, err.column, err.expected),
+            (1, 6, vec!["EOI".to_string()])
+        );
+    }
+
+    #[test]
+    fn rejects_unreadable() {
+        let cases = [
+            (Value::Struct(vec![]), SerializeError::EmptyStruct),
+            (
+                Value::Array(vec![primitive("")]),
+                SerializeError::SingleEmptyElement,
+            ),
+            (
+                primitive("\"x\""),
+                SerializeError::InvalidPrimitive("\"x\"".to_string()),
+            ),
+            (
+                primitive("a,b"),
+                SerializeError::InvalidPrimitive("a,b".to_string()),
+            ),
+            (
+                primitive("(a)"),
+                SerializeError::InvalidPrimitive("(a)".to_string()),
+            ),
+            (
+                Value::Array(vec![primitive("A=1")]),
+                SerializeError::AmbiguousArray("(A=1)".to_string()),
+            ),
+        ];
+        for (value, err) in cases {
+            assert_eq!(serialize_struct(&props(&[("K", value)])), Err(err));
+        }
+        assert_eq!(serialize_struct(&[]), Err(SerializeError::EmptyStruct));
+        assert_eq!(
+            serialize_struct(&props(&[("1K", primitive("1"))])),
+            Err(SerializeError::InvalidName("1K".to_string()))
+        );
+        // fine as long as some element is not a prop
+        let props = props(&[("K", Value::Array(vec![primitive("A=1"), primitive("2")]))]);
+        assert_eq!(roundtrip(&props), props);
+    }
+
+    fn name() -> impl Strategy<Value = String> {
+        "[A-Za-z][A-Za-z0-9_]{0,8}"
+    }
+
+    /// Values [`serialize_struct`] always accepts.
+    fn value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            "[A-Za-z0-9_.+-]{0,8}".prop_map(Value::Primitive),
+            any::<String>().prop_map(Value::String),
+        ];
+        leaf.prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec((name(), inner.clone()), 1..4).prop_map(Value::Struct),
+                prop::collection::vec(inner, 0..4)
+                    .prop_filter("reads back as an empty array", |values| {
+                        !matches!(values.as_slice(), [Value::Primitive(s)] if s.is_empty())
+                    })
+                    .prop_map(Value::Array),
+            ]
+        })
+    }
+
+    /// Anything, including values [`serialize_struct`] has to refuse.
+    fn any_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            "[A-Za-z0-9=,()\" ]{0,6}".prop_map(Value::Primitive),
+            any::<String>().prop_map(Value::String),
+        ];
+        leaf.prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(("[A-Za-z0-9_]{0,4}", inner.clone()), 0..4)
+                    .prop_map(Value::Struct),
+                prop::collection::vec(inner, 0..4).prop_map(Value::Array),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn parse_serialize_roundtrip(props in prop::collection::vec((name(), value()), 1..6)) {
+            let serialized = serialize_struct(&props).unwrap();
+            prop_assert_eq!(parse_struct(&serialized).unwrap(), props);
+        }
+
+        #[test]
+        fn serialize_never_misreads(props in prop::collection::vec(("[A-Za-z0-9_]{0,4}", any_value()), 0..6)) {
+            if let Ok(serialized) = serialize_struct(&props) {
+                prop_assert_eq!(parse_struct(&serialized).unwrap(), props);
+            }
+        }
+    }
 }
@@ -4,26 +4,39 @@ use axum::{
 };
 use thiserror::Error;
 
+pub mod auto_update;
+pub mod config;
 pub mod pal;
+pub mod paths;
 pub mod rcon;
 pub mod steamcmd;
 pub mod game_config;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 enum AppError {
     #[error("error from the inner RCON client")]
     PalworldCommandError(#[from] pal::PalworldCommandError),
     #[error("error during IO")]
     IOError(#[from] std::io::Error),
+    #[error("error in game config")]
+    GameConfigError(#[from] game_config::GameConfigError),
+    #[error("error from SteamCMD")]
+    SteamCMDError(#[from] steamcmd::SteamCMDError),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {:?}", self),
-        )
-            .into_response()
+        match self {
+            // most likely caused by the user's edit, so tell them what is wrong
+            AppError::GameConfigError(err) => err.into_response(),
+            AppError::SteamCMDError(err) => err.into_response(),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {:?}", self),
+            )
+                .into_response(),
+        }
     }
 }
 
@@ -1,21 +1,58 @@
-use axum::{routing::get, Router};
+use axum::{routing::get, Json, Router};
+use clap::Parser;
 use palboard_gateway::{
-    game_config, pal::{self, PalServerClient}, steamcmd
+    auto_update::{self, AutoUpdateConfig}, config::{Args, Config}, game_config::{self, running::{rcon_settings, RconSync, RunningSettings}}, pal::{self, PalServerClient}, steamcmd::{self, job::JobManager}
 };
-use std::env;
+use std::{sync::Arc, time::Duration};
 use tracing::{info, warn};
 
 const VERSION: Option<&str> = option_env!("VERSION");
 
 #[tokio::main]
 async fn main() {
+    let config = Config::load(Args::parse()).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
+    });
+
     console_subscriber::init();
     // tracing_subscriber::fmt::init();
 
+    let paths = Arc::new(config.paths());
+    info!("using {:?}", paths);
+    if paths.stdbuf.is_none() {
+        warn!("stdbuf not found, SteamCMD progress may lag behind");
+    }
+    if !paths.steamcmd.exists() {
+        warn!("SteamCMD not found at {}, updates will fail", paths.steamcmd.display());
+    }
+
+    // `RCONPort` and `AdminPassword` unless pinned by the config
+    let rcon = match std::fs::read_to_string(paths.settings_file()) {
+        Ok(ini) => rcon_settings(&ini),
+        Err(err) => {
+            warn!("failed to read {}, using the default RCON settings: {}", paths.settings_file().display(), err);
+            Default::default()
+        }
+    };
+    if !rcon.enabled {
+        warn!("RCON is disabled in PalWorldSettings.ini, set `RCONEnabled=True` so the gateway can reach the server");
+    }
+    let addr = config.palserver.addr.clone();
+    let password = config.palserver.password.clone();
+    // an address without a port follows `RCONPort`
+    let port_pinned = addr.as_deref().and_then(|a| a.rsplit_once(':')).is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let rcon_sync = RconSync {
+        host: (!port_pinned).then(|| addr.clone().unwrap_or_else(|| "127.0.0.1".to_string())),
+        password: password.is_none(),
+    };
     let client = {
         let mut c = PalServerClient::dial(
-            env::var("PALSERVER_ADDR").expect("you should set `PALSERVER_ADDR` (and optionally `PALSERVER_PASSWORD`) environment variable"), 
-            env::var("PALSERVER_PASSWORD").ok())
+            match &rcon_sync.host {
+                Some(host) => format!("{}:{}", host, rcon.port),
+                None => addr.unwrap(),
+            },
+            password.or(rcon.password))
             .await
             .expect("failed to dial Pal Server");
         let info = c.info().await.expect("failed to get info");
@@ -23,14 +60,40 @@ async fn main() {
         c
     };
 
+    let running = RunningSettings::new(&paths, &client, rcon_sync).await;
+
+    let jobs = JobManager::new(paths.clone());
+    let start_command = config.palserver.start_command.clone();
+    // validated to come with a start command
+    if let (Some(interval), Some(start_command)) = (config.auto_update.interval_minutes, &start_command) {
+        auto_update::spawn(
+            AutoUpdateConfig {
+                interval: Duration::from_secs(interval * 60),
+                deadline: Duration::from_secs(config.auto_update.deadline_minutes.unwrap_or(30) * 60),
+                start_command: start_command.clone(),
+            },
+            client.clone(),
+            jobs.clone(),
+        );
+    }
+
     let app = Router::new()
         .route("/version", get(VERSION.unwrap_or("unknown")))
-        .nest("/pal", pal::route::new_router(client))
-        .nest("/steam", steamcmd::route::new_router())
-        .nest("/game_config", game_config::route::new_router("/home/steam/palserver/"));
+        .route("/config", get(Json(config.redacted())))
+        .nest("/pal", pal::route::new_router(client.clone()))
+        .nest("/steam", steamcmd::route::new_router(jobs))
+        .nest(
+            "/game_config",
+            game_config::route::new_router(
+                &paths,
+                client,
+                start_command,
+                running,
+            ),
+        );
 
-    let listener = tokio::net::TcpListener::bind(env::var("GATEWAY_ADDR").unwrap_or_else(|_| {
-        warn!("you should set `GATEWAY_ADDR` environment variable, frontend will connect to this address");
+    let listener = tokio::net::TcpListener::bind(config.gateway.addr.clone().unwrap_or_else(|| {
+        warn!("you should set `GATEWAY_ADDR` environment variable or `gateway.addr`, frontend will connect to this address");
         "127.0.0.1:8080".to_string()
     }))
     .await
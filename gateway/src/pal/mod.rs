@@ -1,8 +1,7 @@
 use std::{sync::Arc, time::Duration};
 use tokio::{
-    net::ToSocketAddrs,
-    sync::{mpsc, oneshot},
-    time::timeout,
+    sync::{mpsc, oneshot, Mutex},
+    time::{sleep, timeout},
 };
 
 use crate::rcon::RCONClient;
@@ -11,10 +10,37 @@ use tracing::instrument;
 
 pub mod route;
 
+/// RCON client shared by all handlers, dialing again whenever the connection is lost
+/// (e.g. after a server restart).
 #[derive(Debug, Clone)]
 pub struct PalServerClient {
-    tx: mpsc::Sender<(String, oneshot::Sender<tokio::io::Result<String>>)>,
-    _runner: Arc<tokio::task::JoinHandle<()>>,
+    conn: Arc<Mutex<Connection>>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    target: DialTarget,
+    tx: mpsc::Sender<CommandReciple>,
+    _runner: tokio::task::JoinHandle<()>,
+    watcher: Option<Arc<dyn RestartWatcher>>,
+}
+
+/// Where the client dials, and the password it logs in with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialTarget {
+    pub addr: String,
+    pub password: Option<String>,
+}
+
+/// Asked whenever the connection was lost, so the client follows the `RCONPort` and
+/// `AdminPassword` the server restarted with, however it was restarted.
+pub trait RestartWatcher: Send + Sync + std::fmt::Debug {
+    /// Where the server answers once it has restarted, given where it answered so far.
+    fn next_target(&self, current: &DialTarget) -> DialTarget;
+    /// The server answered again after the connection was lost, at the target from the last
+    /// [`RestartWatcher::next_target`] call. A connection lost otherwise, with the same target,
+    /// passes for a restart too.
+    fn restarted(&self);
 }
 
 #[derive(Error, Debug)]
@@ -38,10 +64,16 @@ impl PalServerClient {
                 Ok(None) => break,
                 Ok(Some((command, tx))) => {
                     let result = client.exec(command).await;
+                    // the connection is unusable after an IO error, let the next command redial
+                    let failed = result.is_err();
                     if tx.send(result).is_err() {
                         tracing::error!("failed to send result back");
                         break;
                     };
+                    if failed {
+                        tracing::error!("command failed, closing connection");
+                        break;
+                    }
                 }
                 Err(_) => {
                     // timeout, send a keepalive
@@ -54,18 +86,80 @@ impl PalServerClient {
             }
         }
     }
+    async fn spawn_runner(
+        target: &DialTarget,
+    ) -> tokio::io::Result<(mpsc::Sender<CommandReciple>, tokio::task::JoinHandle<()>)> {
+        let client = RCONClient::dial(&target.addr, target.password.as_deref()).await?;
+        let (tx, rx) = mpsc::channel(32);
+        let runner = tokio::spawn(Self::task_runner(client, rx));
+        Ok((tx, runner))
+    }
     pub async fn dial(
-        addr: impl ToSocketAddrs,
+        addr: impl ToString,
         password: Option<impl ToString>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let client = RCONClient::dial(addr, password).await?;
-        let (tx, rx) = mpsc::channel(32);
-        let _runner = Arc::new(tokio::spawn(Self::task_runner(client, rx)));
-        Ok(Self { tx, _runner })
+        let target = DialTarget {
+            addr: addr.to_string(),
+            password: password.map(|p| p.to_string()),
+        };
+        let (tx, _runner) = Self::spawn_runner(&target).await?;
+        let conn = Connection {
+            target,
+            tx,
+            _runner,
+            watcher: None,
+        };
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+    /// Asks `watcher` where to dial whenever the connection was lost, from now on.
+    pub async fn watch_restarts(&self, watcher: Arc<dyn RestartWatcher>) {
+        self.conn.lock().await.watcher = Some(watcher);
+    }
+    /// Sender to a live runner, dialing again if the previous one has stopped.
+    async fn sender(&self) -> PalResult<mpsc::Sender<CommandReciple>> {
+        let mut conn = self.conn.lock().await;
+        if conn.tx.is_closed() {
+            Self::redial(&mut conn).await?;
+        }
+        Ok(conn.tx.clone())
+    }
+    /// Dials the target the watcher expects after a restart, or the previous one if the server
+    /// has not restarted with it (yet).
+    async fn redial(conn: &mut Connection) -> tokio::io::Result<()> {
+        let next = conn.watcher.as_ref().map(|w| w.next_target(&conn.target));
+        if let Some(next) = next.as_ref().filter(|next| **next != conn.target) {
+            tracing::info!(
+                "RCON connection lost, dialing {} with the settings the server restarts with",
+                next.addr
+            );
+            match Self::spawn_runner(next).await {
+                Ok((tx, runner)) => {
+                    (conn.tx, conn._runner) = (tx, runner);
+                    conn.target = next.clone();
+                    if let Some(watcher) = &conn.watcher {
+                        watcher.restarted();
+                    }
+                    return Ok(());
+                }
+                Err(err) => tracing::info!("not answering yet: {}", err),
+            }
+        }
+        tracing::info!("RCON connection lost, dialing {} again", conn.target.addr);
+        (conn.tx, conn._runner) = Self::spawn_runner(&conn.target).await?;
+        // the settings it answers with did not change, others may have
+        if next.as_ref() == Some(&conn.target) {
+            if let Some(watcher) = &conn.watcher {
+                watcher.restarted();
+            }
+        }
+        Ok(())
     }
     async fn exec(&mut self, command: String) -> Result<String, PalworldCommandError> {
         let (tx, rx) = oneshot::channel();
-        self.tx
+        self.sender()
+            .await?
             .send((command, tx))
             .await
             .map_err(PalworldCommandError::RunnerDroppedCommandRx)?;
@@ -100,10 +194,49 @@ impl PalServerClient {
     pub async fn show_players(&mut self) -> PalResult<String> {
         self.exec("ShowPlayers".to_string()).await
     }
+    /// Number of players online, from the rows of `ShowPlayers` after its header.
+    pub async fn player_count(&mut self) -> PalResult<usize> {
+        let body = self.show_players().await?;
+        Ok(body
+            .lines()
+            .skip(1)
+            .filter(|l| !l.trim().is_empty())
+            .count())
+    }
     pub async fn info(&mut self) -> PalResult<String> {
         self.exec("Info".to_string()).await
     }
     pub async fn save(&mut self) -> PalResult<String> {
         self.exec("Save".to_string()).await
     }
+    /// Polls `Info` until the server stops answering, `false` if it is still up after `limit`.
+    pub async fn wait_for_exit(&mut self, limit: Duration) -> bool {
+        timeout(limit, async {
+            loop {
+                // the first failure may only be a dropped connection, the retry dials again
+                if self.info().await.is_err() && self.info().await.is_err() {
+                    break;
+                }
+                sleep(Duration::from_secs(2)).await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+/// Runs the user-provided command that starts the server (e.g. `systemctl start palserver`)
+/// in the background, logging its exit.
+pub fn start_server(command: &str) -> std::io::Result<()> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .spawn()?;
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) => tracing::info!("start command exited with {}", status),
+            Err(err) => tracing::error!("failed to wait for start command: {}", err),
+        }
+    });
+    Ok(())
 }
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use crate::game_config::CONFIG_DIR;
+
+/// Where stdbuf is looked for unless configured.
+const STDBUF_CANDIDATES: &[&str] = &["/bin/stdbuf", "/usr/bin/stdbuf"];
+
+/// Where SteamCMD, the server and the gateway's own files are. The defaults follow the
+/// cm2network/steamcmd image.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    /// `steamcmd.sh`, or `steamcmd` when installed by a package manager.
+    pub steamcmd: PathBuf,
+    /// `force_install_dir` of the server.
+    pub install_dir: PathBuf,
+    /// Where `PalWorldSettings.ini` and the other config files are.
+    pub config_dir: PathBuf,
+    pub save_dir: PathBuf,
+    /// Config history, presets and such.
+    pub data_dir: PathBuf,
+    /// Unbuffers SteamCMD's output, progress arrives in bursts without it.
+    pub stdbuf: Option<PathBuf>,
+}
+
+impl Paths {
+    /// Dirs inside the install dir follow it unless set separately.
+    pub fn new(install_dir: impl Into<PathBuf>) -> Self {
+        let install_dir = install_dir.into();
+        Paths {
+            steamcmd: "/home/steam/steamcmd/steamcmd.sh".into(),
+            config_dir: install_dir.join(CONFIG_DIR),
+            save_dir: install_dir.join("Pal/Saved/SaveGames"),
+            data_dir: install_dir.join("palboard"),
+            install_dir,
+            stdbuf: STDBUF_CANDIDATES
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.exists()),
+        }
+    }
+
+    pub fn settings_file(&self) -> PathBuf {
+        self.config_dir.join("PalWorldSettings.ini")
+    }
+
+    pub fn default_settings_file(&self) -> PathBuf {
+        self.install_dir.join("DefaultPalWorldSettings.ini")
+    }
+
+    /// Written by SteamCMD for the installed app.
+    pub fn app_manifest(&self, app_id: &str) -> PathBuf {
+        self.install_dir
+            .join("steamapps")
+            .join(format!("appmanifest_{}.acf", app_id))
+    }
+}
@@ -75,13 +75,10 @@ impl Encoder<RCONMessage> for RCONCodec {
     type Error = std::io::Error; // TODO: use custom error type
 
     fn encode(&mut self, item: RCONMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        if item.body.as_bytes().len() + 10 > 4096 {
+        if item.body.len() + 10 > 4096 {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
-                format!(
-                    "Frame of length {} is too large.",
-                    item.body.as_bytes().len() + 10
-                ),
+                format!("Frame of length {} is too large.", item.body.len() + 10),
             ));
         }
 
@@ -0,0 +1,63 @@
+use std::{fmt, io::Write, os::unix::fs::OpenOptionsExt, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths::Paths;
+
+/// The Steam account SteamCMD logs in with instead of `anonymous`. Only the username is stored,
+/// SteamCMD caches the session itself, so the password is needed for the first login only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub username: String,
+}
+
+/// A password or Steam Guard code, kept out of logs and responses.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+fn account_file(paths: &Paths) -> PathBuf {
+    paths.data_dir.join("steam_account.json")
+}
+
+/// `None` when logging in anonymously.
+pub fn read_account(paths: &Paths) -> std::io::Result<Option<Account>> {
+    match std::fs::read(account_file(paths)) {
+        Ok(account) => Ok(Some(serde_json::from_slice(&account)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub(super) fn write_account(paths: &Paths, account: &Account) -> std::io::Result<()> {
+    std::fs::create_dir_all(&paths.data_dir)?;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(account_file(paths))?;
+    file.write_all(&serde_json::to_vec(account)?)
+}
+
+/// Goes back to logging in anonymously, `false` if that was the case already. The session stays
+/// cached by SteamCMD.
+pub fn remove_account(paths: &Paths) -> std::io::Result<bool> {
+    match std::fs::remove_file(account_file(paths)) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
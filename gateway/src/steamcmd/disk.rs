@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::Serialize;
+
+use crate::paths::Paths;
+
+use super::{
+    app_info_value, read_app_manifest, vdf::Value, SteamCMDError, SteamCMDResult, UpdateType,
+};
+
+/// `StateFlags` bits of the app manifest.
+const STATE_UPDATE_REQUIRED: u32 = 0x2;
+const STATE_FULLY_INSTALLED: u32 = 0x4;
+const STATE_FILES_MISSING: u32 = 0x20;
+const STATE_FILES_CORRUPT: u32 = 0x80;
+const STATE_UPDATE_PAUSED: u32 = 0x200;
+
+/// Space on the install volume and what an update of the game would take of it. Sizes are in
+/// bytes.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskReport {
+    /// Available to the gateway's user on the volume of the install dir.
+    pub free: u64,
+    pub total: u64,
+    /// Everything under the install dir, including the saves if they are inside it.
+    pub install_size: u64,
+    pub save_size: u64,
+    /// `None` if the game is not installed.
+    pub installed: Option<InstallState>,
+    /// `None` without app info, or if it lists no sizes for the branch.
+    pub latest: Option<BuildSize>,
+    /// Estimated free space updating the game to `latest` needs.
+    pub required: Option<u64>,
+    pub sufficient: Option<bool>,
+}
+
+/// What SteamCMD recorded about the install in the app manifest, its flags are only updated
+/// by SteamCMD itself, e.g. when validating.
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallState {
+    pub branch: String,
+    pub build_id: String,
+    pub size_on_disk: u64,
+    pub state_flags: u32,
+    pub fully_installed: bool,
+    pub update_required: bool,
+    pub files_missing: bool,
+    pub files_corrupt: bool,
+    /// Also set when an update stopped because the disk was full.
+    pub update_paused: bool,
+}
+
+/// Sizes of the latest build of a branch, summed over its Linux depots.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildSize {
+    pub branch: String,
+    pub size: u64,
+    /// Compressed, SteamCMD keeps the chunks until the update is committed.
+    pub download: u64,
+    pub depots: BTreeMap<u64, DepotSize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DepotSize {
+    pub size: u64,
+    pub download: u64,
+}
+
+impl DiskReport {
+    /// Estimated free space `update_type` needs, `None` if unknown or if it installs nothing.
+    pub fn required_for(&self, update_type: &UpdateType) -> Option<u64> {
+        let latest = self.latest.as_ref()?;
+        match update_type {
+            UpdateType::Game { .. } => {
+                let installed = self.installed.as_ref().map_or(0, |app| app.size_on_disk);
+                Some(latest.download + latest.size.saturating_sub(installed))
+            }
+            // downloaded whole next to the install, then copied over it
+            UpdateType::Depot { depot, .. } => Some(latest.depots.get(depot)?.size),
+            UpdateType::Steam | UpdateType::AppInfo | UpdateType::Login { .. } => None,
+        }
+    }
+
+    /// Refuses updates that would most likely fill the disk.
+    pub fn check(&self, update_type: &UpdateType) -> SteamCMDResult<()> {
+        match self.required_for(update_type) {
+            Some(required) if required > self.free => Err(SteamCMDError::InsufficientSpace {
+                required,
+                free: self.free,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Measures the install and save dirs and compares them with `branch` in the output of an
+/// [`UpdateType::AppInfo`] job, the installed branch by default.
+pub async fn disk_report(
+    paths: &Paths,
+    app_info_output: Option<&str>,
+    branch: Option<&str>,
+) -> SteamCMDResult<DiskReport> {
+    let manifest = read_app_manifest(paths).await?;
+    let installed = manifest.map(|app| {
+        let flag = |bit| app.state_flags & bit != 0;
+        InstallState {
+            branch: app
+                .user_config
+                .betakey
+                .clone()
+                .filter(|branch| !branch.is_empty())
+                .unwrap_or_else(|| "public".to_string()),
+            build_id: app.buildid.clone(),
+            size_on_disk: app.size_on_disk,
+            state_flags: app.state_flags,
+            fully_installed: flag(STATE_FULLY_INSTALLED),
+            update_required: flag(STATE_UPDATE_REQUIRED),
+            files_missing: flag(STATE_FILES_MISSING),
+            files_corrupt: flag(STATE_FILES_CORRUPT),
+            update_paused: flag(STATE_UPDATE_PAUSED),
+        }
+    });
+    let branch = branch
+        .or(installed.as_ref().map(|app| app.branch.as_str()))
+        .unwrap_or("public");
+    let latest = match app_info_output {
+        Some(output) => build_size(&app_info_value(output)?, branch),
+        None => None,
+    };
+
+    let install_dir = paths.install_dir.clone();
+    let save_dir = paths.save_dir.clone();
+    let (free, total, install_size, save_size) = tokio::task::spawn_blocking(move || {
+        // statvfs needs an existing path, the install dir may not be yet
+        let volume = install_dir
+            .ancestors()
+            .find(|dir| dir.exists())
+            .unwrap_or(Path::new("/"));
+        let stat = nix::sys::statvfs::statvfs(volume)?;
+        let block_size = stat.fragment_size() as u64;
+        std::io::Result::Ok((
+            stat.blocks_available() as u64 * block_size,
+            stat.blocks() as u64 * block_size,
+            dir_size(&install_dir)?,
+            dir_size(&save_dir)?,
+        ))
+    })
+    .await
+    .map_err(std::io::Error::from)??;
+
+    let mut report = DiskReport {
+        free,
+        total,
+        install_size,
+        save_size,
+        installed,
+        latest,
+        required: None,
+        sufficient: None,
+    };
+    let update = UpdateType::Game {
+        validate: false,
+        branch: None,
+        branch_password: None,
+    };
+    report.required = report.required_for(&update);
+    report.sufficient = report.required.map(|required| required <= free);
+    Ok(report)
+}
+
+/// Reads the sizes of `branch` from the depots of the app info. Depots for other platforms are
+/// left out, as are those without sizes, which older app info does not list.
+fn build_size(app_info: &Value, branch: &str) -> Option<BuildSize> {
+    let mut depots = BTreeMap::new();
+    for (id, depot) in app_info.get("depots")?.as_object()? {
+        // the others are `branches` and such
+        let Ok(id) = id.parse() else { continue };
+        let oslist = depot.pointer(&["config", "oslist"]).and_then(Value::as_str);
+        if oslist.is_some_and(|oslist| !oslist.split(',').any(|os| os.trim() == "linux")) {
+            continue;
+        }
+        let Some(manifest) = depot.pointer(&["manifests", branch]) else {
+            continue;
+        };
+        let number = |key| manifest.get(key)?.as_str()?.parse().ok();
+        if let (Some(size), Some(download)) = (number("size"), number("download")) {
+            depots.insert(id, DepotSize { size, download });
+        }
+    }
+    if depots.is_empty() {
+        return None;
+    }
+    Some(BuildSize {
+        branch: branch.to_string(),
+        size: depots.values().map(|depot| depot.size).sum(),
+        download: depots.values().map(|depot| depot.download).sum(),
+        depots,
+    })
+}
+
+/// Apparent size of the files under `dir`, without following symlinks. 0 if it does not exist.
+fn dir_size(dir: &Path) -> std::io::Result<u64> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.path().symlink_metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
@@ -0,0 +1,513 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    os::unix::process::ExitStatusExt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use nix::sys::signal::Signal;
+use serde::Serialize;
+use tokio::{
+    io::AsyncWriteExt,
+    process::ChildStdin,
+    sync::broadcast,
+    time::{sleep_until, Instant},
+};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::paths::Paths;
+
+use super::{
+    account::read_account,
+    after_success,
+    parser::{parse_line, parse_prompt, Prompt},
+    run_steamcmd, signal_group, update_args_for, SteamCMDError, SteamCMDResult, UpdateSteamMessage,
+    UpdateType,
+};
+
+pub type JobId = u64;
+
+/// Finished jobs kept around for late subscribers, older ones are forgotten.
+const MAX_FINISHED_JOBS: usize = 16;
+const EVENT_BUFFER_SIZE: usize = 1024;
+/// How long SteamCMD may take to stop after SIGINT before it is killed.
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// Error lines kept per job, older ones are dropped.
+const MAX_ERROR_LINES: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    /// `code` is `None` if SteamCMD was killed by `signal`.
+    Exited {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    Cancelled,
+    Failed {
+        reason: String,
+    },
+}
+
+/// Everything a job produced, in order.
+#[derive(Debug, Clone)]
+pub enum JobEvent {
+    /// Raw output of SteamCMD.
+    Output(Bytes),
+    /// Parsed from a line of output.
+    Message(UpdateSteamMessage),
+    /// Always the last event.
+    Finished(JobResult),
+}
+
+/// How a job ended.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobResult {
+    #[serde(flatten)]
+    pub status: JobStatus,
+    /// Seconds, up to now while running.
+    pub duration: f64,
+    /// The last lines SteamCMD reported an error in, oldest first.
+    pub errors: Vec<String>,
+}
+
+impl JobResult {
+    pub fn is_success(&self) -> bool {
+        matches!(self.status, JobStatus::Exited { code: Some(0), .. })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub update_type: UpdateType,
+    /// Unix seconds.
+    pub started_at: u64,
+    #[serde(flatten)]
+    pub result: JobResult,
+}
+
+/// A SteamCMD run that keeps going whether anyone is watching or not.
+#[derive(Debug)]
+pub struct Job {
+    pub id: JobId,
+    pub update_type: UpdateType,
+    pub started_at: u64,
+    start: Instant,
+    state: Mutex<JobState>,
+    tx: broadcast::Sender<JobEvent>,
+    cancel: CancellationToken,
+    /// Only kept by login jobs, the others get EOF when prompted.
+    stdin: tokio::sync::Mutex<Option<ChildStdin>>,
+}
+
+#[derive(Debug)]
+struct JobState {
+    status: JobStatus,
+    events: Vec<JobEvent>,
+    errors: VecDeque<String>,
+    duration: Option<Duration>,
+}
+
+impl Job {
+    pub fn info(&self) -> JobInfo {
+        JobInfo {
+            id: self.id,
+            update_type: self.update_type.clone(),
+            started_at: self.started_at,
+            result: self.result(),
+        }
+    }
+
+    /// The final result once finished, the one so far while running.
+    pub fn result(&self) -> JobResult {
+        let state = self.state.lock().unwrap();
+        JobResult {
+            status: state.status.clone(),
+            duration: state
+                .duration
+                .unwrap_or_else(|| self.start.elapsed())
+                .as_secs_f64(),
+            errors: state.errors.iter().cloned().collect(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state.lock().unwrap().status, JobStatus::Running)
+    }
+
+    /// Past events, and a receiver for the following ones unless the job has finished.
+    pub fn subscribe(&self) -> (Vec<JobEvent>, Option<broadcast::Receiver<JobEvent>>) {
+        let state = self.state.lock().unwrap();
+        // subscribing under the lock so no event is missed or seen twice
+        let rx = matches!(state.status, JobStatus::Running).then(|| self.tx.subscribe());
+        (state.events.clone(), rx)
+    }
+
+    /// Asks SteamCMD to stop, `false` if the job has finished already.
+    pub fn cancel(&self) -> bool {
+        let running = self.is_running();
+        if running {
+            self.cancel.cancel();
+        }
+        running
+    }
+
+    /// Answers a prompt, `false` if the job takes no input.
+    pub async fn input(&self, text: &str) -> bool {
+        let mut stdin = self.stdin.lock().await;
+        let Some(pipe) = stdin.as_mut() else {
+            return false;
+        };
+        let line = format!("{}\n", text);
+        if let Err(err) = pipe.write_all(line.as_bytes()).await {
+            warn!("failed to write to SteamCMD: {}", err);
+            *stdin = None;
+            return false;
+        }
+        true
+    }
+
+    /// Waits for the job to finish.
+    pub async fn finished(&self) -> JobResult {
+        if let (_, Some(mut rx)) = self.subscribe() {
+            loop {
+                match rx.recv().await {
+                    Ok(JobEvent::Finished(result)) => return result,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                    _ => {}
+                }
+            }
+        }
+        self.result()
+    }
+
+    /// Waits for the job to finish, then its output unless SteamCMD failed.
+    pub async fn successful_output(&self) -> SteamCMDResult<String> {
+        let result = self.finished().await;
+        if !result.is_success() {
+            return Err(SteamCMDError::JobFailed {
+                id: self.id,
+                result,
+            });
+        }
+        Ok(String::from_utf8_lossy(&self.output()).into_owned())
+    }
+
+    /// Everything SteamCMD has printed so far.
+    pub fn output(&self) -> Vec<u8> {
+        let state = self.state.lock().unwrap();
+        state
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                JobEvent::Output(data) => Some(data.as_ref()),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// Records a line SteamCMD reported an error in.
+    fn push_error(&self, line: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.errors.len() == MAX_ERROR_LINES {
+            state.errors.pop_front();
+        }
+        state.errors.push_back(line.to_string());
+    }
+
+    /// Pushes the last event, subscribers see the job as running until then.
+    fn finish(&self, status: JobStatus) {
+        let duration = self.start.elapsed();
+        let mut state = self.state.lock().unwrap();
+        state.duration = Some(duration);
+        let result = JobResult {
+            status,
+            duration: duration.as_secs_f64(),
+            errors: state.errors.iter().cloned().collect(),
+        };
+        drop(state);
+        if result.is_success() {
+            info!("job {} succeeded in {:.1}s", self.id, result.duration);
+        } else {
+            warn!("job {} did not succeed: {:?}", self.id, result);
+        }
+        self.push(JobEvent::Finished(result));
+    }
+
+    fn push(&self, event: JobEvent) {
+        let mut state = self.state.lock().unwrap();
+        if let JobEvent::Finished(result) = &event {
+            state.status = result.status.clone();
+        }
+        state.events.push(event.clone());
+        // no subscribers is fine
+        let _ = self.tx.send(event);
+    }
+}
+
+/// All SteamCMD jobs, running or recently finished.
+#[derive(Debug, Clone)]
+pub struct JobManager {
+    paths: Arc<Paths>,
+    jobs: Arc<Mutex<Jobs>>,
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    next_id: JobId,
+    jobs: BTreeMap<JobId, Arc<Job>>,
+}
+
+impl JobManager {
+    pub fn new(paths: Arc<Paths>) -> Self {
+        JobManager {
+            paths,
+            jobs: Default::default(),
+        }
+    }
+
+    pub fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    /// Starts SteamCMD unless it is running already, only one instance may touch the install
+    /// directory at a time.
+    pub fn start(&self, update_type: UpdateType) -> SteamCMDResult<Arc<Job>> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(running) = jobs.jobs.values().find(|job| job.is_running()) {
+            return Err(SteamCMDError::Busy(running.id));
+        }
+        let account = read_account(&self.paths).unwrap_or_else(|err| {
+            warn!(
+                "failed to read the Steam account, logging in anonymously: {}",
+                err
+            );
+            None
+        });
+        let args = update_args_for(&self.paths, account.as_ref(), &update_type);
+        let (child, stdin, stdout) = run_steamcmd(&self.paths, args)?;
+
+        // none is running, so these are all finished
+        let expired: Vec<_> = jobs
+            .jobs
+            .keys()
+            .take(jobs.jobs.len().saturating_sub(MAX_FINISHED_JOBS - 1))
+            .copied()
+            .collect();
+        for id in expired {
+            jobs.jobs.remove(&id);
+        }
+
+        let id = jobs.next_id;
+        jobs.next_id += 1;
+        let stdin = matches!(update_type, UpdateType::Login { .. }).then_some(stdin);
+        let job = Arc::new(Job {
+            id,
+            update_type,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            start: Instant::now(),
+            state: Mutex::new(JobState {
+                status: JobStatus::Running,
+                events: Vec::new(),
+                errors: VecDeque::new(),
+                duration: None,
+            }),
+            tx: broadcast::channel(EVENT_BUFFER_SIZE).0,
+            cancel: CancellationToken::new(),
+            stdin: tokio::sync::Mutex::new(stdin),
+        });
+        jobs.jobs.insert(id, job.clone());
+        info!("started job {} ({:?})", job.id, job.update_type);
+
+        tokio::spawn(drive(job.clone(), self.paths.clone(), child, stdout));
+        Ok(job)
+    }
+
+    /// The job currently running, if any.
+    pub fn running(&self) -> Option<Arc<Job>> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.jobs.values().find(|job| job.is_running()).cloned()
+    }
+
+    pub fn get(&self, id: JobId) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().jobs.get(&id).cloned()
+    }
+
+    /// Oldest first.
+    pub fn list(&self) -> Vec<Arc<Job>> {
+        self.jobs.lock().unwrap().jobs.values().cloned().collect()
+    }
+
+    /// Output of the latest successful [`UpdateType::AppInfo`] job still kept, if any.
+    pub fn last_app_info(&self) -> Option<String> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.jobs.values().rev().find(|job| {
+            matches!(job.update_type, UpdateType::AppInfo) && job.result().is_success()
+        })?;
+        Some(String::from_utf8_lossy(&job.output()).into_owned())
+    }
+}
+
+/// Pipes SteamCMD's output into the job until it exits.
+#[instrument(skip_all, fields(id = job.id))]
+async fn drive(
+    job: Arc<Job>,
+    paths: Arc<Paths>,
+    mut child: tokio::process::Child,
+    mut stdout: tokio_util::io::ReaderStream<tokio::process::ChildStdout>,
+) {
+    let _guard = FinishGuard(job.clone());
+    let mut line = Vec::new();
+    let logged_in = AtomicBool::new(false);
+    let parse = |line: &[u8]| {
+        let text = String::from_utf8_lossy(line);
+        let text = text.strip_suffix('\r').unwrap_or(&text);
+        debug!("parsing line: {}", text);
+        if let Some(msg) = parse_line(text) {
+            match msg {
+                UpdateSteamMessage::Error { .. } => job.push_error(text),
+                UpdateSteamMessage::LoggedIn { .. } => logged_in.store(true, Ordering::Relaxed),
+                _ => {}
+            }
+            job.push(JobEvent::Message(msg));
+        }
+    };
+    // prompts end without a newline, each is handled once
+    let mut prompted = false;
+    let mut password_sent = false;
+    let mut interrupted_at = None;
+    let mut killed = false;
+    loop {
+        let chunk = tokio::select! {
+            chunk = stdout.next() => chunk,
+            _ = job.cancel.cancelled(), if interrupted_at.is_none() => {
+                info!("cancelling, interrupting SteamCMD");
+                if let Err(err) = signal_group(&child, Signal::SIGINT) {
+                    warn!("failed to interrupt SteamCMD: {}", err);
+                }
+                interrupted_at = Some(Instant::now());
+                continue;
+            }
+            _ = sleep_until(interrupted_at.unwrap_or_else(Instant::now) + CANCEL_GRACE_PERIOD),
+                if interrupted_at.is_some() && !killed =>
+            {
+                warn!("SteamCMD still running {:?} after SIGINT, killing it", CANCEL_GRACE_PERIOD);
+                if let Err(err) = signal_group(&child, Signal::SIGKILL) {
+                    error!("failed to kill SteamCMD: {}", err);
+                }
+                killed = true;
+                continue;
+            }
+        };
+        let Some(chunk) = chunk else { break };
+        let data = match chunk {
+            Ok(data) => data,
+            Err(err) => {
+                error!("while piping: {}", err);
+                break;
+            }
+        };
+        job.push(JobEvent::Output(data.clone()));
+
+        for &byte in data.iter() {
+            if byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            parse(&line);
+            line.clear();
+            prompted = false;
+        }
+
+        if prompted {
+            continue;
+        }
+        if let Some(prompt) = parse_prompt(&String::from_utf8_lossy(&line)) {
+            prompted = true;
+            // the password is sent once, so a wrong one is asked for instead of retried
+            if let (
+                Prompt::Password,
+                UpdateType::Login {
+                    password: Some(password),
+                    ..
+                },
+            ) = (prompt, &job.update_type)
+            {
+                if !password_sent {
+                    password_sent = true;
+                    debug!("sending the password");
+                    if job.input(password.expose()).await {
+                        continue;
+                    }
+                }
+            }
+            info!("SteamCMD prompts for {:?}", prompt);
+            job.push(JobEvent::Message(UpdateSteamMessage::Prompt { prompt }));
+        }
+    }
+    if !line.is_empty() {
+        parse(&line);
+    }
+
+    debug!("waiting for child exit");
+    let status = match child.wait().await {
+        Ok(_) if job.cancel.is_cancelled() => {
+            job.push(JobEvent::Message(UpdateSteamMessage::Cancelled));
+            JobStatus::Cancelled
+        }
+        Ok(status) => JobStatus::Exited {
+            code: status.code(),
+            signal: status.signal(),
+        },
+        Err(err) => JobStatus::Failed {
+            reason: err.to_string(),
+        },
+    };
+    let status = match status {
+        JobStatus::Exited { code: Some(0), .. }
+            if matches!(job.update_type, UpdateType::Login { .. })
+                && !logged_in.load(Ordering::Relaxed) =>
+        {
+            JobStatus::Failed {
+                reason: "SteamCMD exited without logging in".to_string(),
+            }
+        }
+        JobStatus::Exited { code: Some(0), .. } => {
+            match after_success(&paths, &job.update_type).await {
+                Ok(()) => status,
+                Err(err) => JobStatus::Failed {
+                    reason: format!("SteamCMD succeeded, but then: {}", err),
+                },
+            }
+        }
+        status => status,
+    };
+    job.finish(status);
+}
+
+/// Marks the job as failed if [`drive`] stops before it finishes, e.g. by panicking, so it does
+/// not block the next job forever.
+struct FinishGuard(Arc<Job>);
+
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        if self.0.is_running() {
+            self.0.finish(JobStatus::Failed {
+                reason: "stopped following SteamCMD unexpectedly".to_string(),
+            });
+        }
+    }
+}
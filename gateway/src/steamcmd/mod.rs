@@ -1,70 +1,499 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use thiserror::Error;
-use tokio::process::{Child, ChildStdout, Command};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
 use tokio_util::io::ReaderStream;
-use tracing::error;
 
+use crate::paths::Paths;
+
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
+pub mod account;
+pub mod disk;
+pub mod job;
+pub mod parser;
 pub mod route;
+pub mod vdf;
+
+pub use parser::UpdateSteamMessage;
+
+use account::{Account, Secret};
 
-const STEAMCMD_EXE: &str = "/home/steam/steamcmd/steamcmd.sh"; // as in cm2network/steamcmd
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum UpdateType {
     Steam,
-    Game { validate: bool },
+    Game {
+        validate: bool,
+        /// Beta branch to install, `public` to leave a beta.
+        branch: Option<String>,
+        #[serde(skip_serializing)]
+        branch_password: Option<Secret>,
+    },
+    /// Installs a specific build of a depot over the game, to pin it or roll back.
+    Depot {
+        depot: u64,
+        manifest: u64,
+    },
+    /// Only fetches the latest app info, see [`update_check`].
+    AppInfo,
+    /// Logs in to store the account for the following jobs, see [`account`]. SteamCMD prompts
+    /// for the password unless it has a session cached.
+    Login {
+        username: String,
+        #[serde(skip_serializing)]
+        password: Option<Secret>,
+    },
 }
+
+impl UpdateType {
+    /// Whether a job of this type does what `other` asks for, so a client asking for `other`
+    /// may follow it. Logins never do, their prompts are for whoever started them.
+    pub fn does(&self, other: &UpdateType) -> bool {
+        match (self, other) {
+            (UpdateType::Steam, UpdateType::Steam) | (UpdateType::AppInfo, UpdateType::AppInfo) => {
+                true
+            }
+            (
+                UpdateType::Game {
+                    validate, branch, ..
+                },
+                UpdateType::Game {
+                    validate: other_validate,
+                    branch: other_branch,
+                    ..
+                },
+            ) => validate == other_validate && branch == other_branch,
+            (
+                UpdateType::Depot { depot, manifest },
+                UpdateType::Depot {
+                    depot: other_depot,
+                    manifest: other_manifest,
+                },
+            ) => depot == other_depot && manifest == other_manifest,
+            _ => false,
+        }
+    }
+}
+
 const STEAMCMD_UPDATE_ARGS: &[&str] = &["+login", "anonymous", "+quit"];
-const STEAMCMD_UPDATE_GAME_ARGS: &[&str] = &[
-    "+force_install_dir",
-    "/home/steam/palserver",
+const STEAMCMD_APP_INFO_ARGS: &[&str] = &[
     "+login",
     "anonymous",
-    "+app_update",
+    // otherwise the cached info may be outdated
+    "+app_info_update",
+    "1",
+    "+app_info_print",
     "2394010",
-    "validate",
     "+quit",
 ];
-const STEAMCMD_UPDATE_GAME_NO_VALIDATE_ARGS: &[&str] = &[
-    "+force_install_dir",
-    "/home/steam/palserver",
-    "+login",
-    "anonymous",
-    "+app_update",
-    "2394010",
-    "+quit",
-];
-fn update_args_for(update_type: UpdateType) -> &'static [&'static str] {
-    match update_type {
-        UpdateType::Steam => STEAMCMD_UPDATE_ARGS,
-        UpdateType::Game { validate: true } => STEAMCMD_UPDATE_GAME_ARGS,
-        UpdateType::Game { .. } => STEAMCMD_UPDATE_GAME_NO_VALIDATE_ARGS,
-    }
+/// Installs log in with `account` if any.
+fn update_args_for(
+    paths: &Paths,
+    account: Option<&Account>,
+    update_type: &UpdateType,
+) -> Vec<String> {
+    let strings = |args: &[&str]| args.iter().map(|a| a.to_string()).collect();
+    let install_args = match update_type {
+        UpdateType::Steam => return strings(STEAMCMD_UPDATE_ARGS),
+        UpdateType::AppInfo => return strings(STEAMCMD_APP_INFO_ARGS),
+        UpdateType::Login { username, .. } => {
+            return vec!["+login".to_string(), username.clone(), "+quit".to_string()]
+        }
+        UpdateType::Game {
+            validate,
+            branch,
+            branch_password,
+        } => {
+            let mut args = vec!["+app_update".to_string(), APP_ID.to_string()];
+            if let Some(branch) = branch {
+                args.extend(["-beta".to_string(), branch.clone()]);
+                // only means something along with `-beta`
+                if let Some(password) = branch_password {
+                    args.extend(["-betapassword".to_string(), password.expose().to_string()]);
+                }
+            }
+            if *validate {
+                args.push("validate".to_string());
+            }
+            args
+        }
+        UpdateType::Depot { depot, manifest } => vec![
+            "+download_depot".to_string(),
+            APP_ID.to_string(),
+            depot.to_string(),
+            manifest.to_string(),
+        ],
+    };
+    let login_args = match account {
+        // fail instead of waiting for a password once the cached session expires
+        Some(account) => vec!["+@NoPromptForPassword", "1", "+login", &account.username],
+        None => vec!["+login", "anonymous"],
+    };
+    [
+        "+force_install_dir".to_string(),
+        paths.install_dir.to_string_lossy().into_owned(),
+    ]
+    .into_iter()
+    .chain(login_args.into_iter().map(String::from))
+    .chain(install_args)
+    .chain(["+quit".to_string()])
+    .collect()
 }
-const BUFFER_SIZE: usize = 128;
 
 #[derive(Error, Debug)]
 pub enum SteamCMDError {
     #[error("during spawn")]
     SpawnError(std::io::Error),
+    #[error("SteamCMD is already running as job {0}")]
+    Busy(job::JobId),
+    #[error("while reading the app manifest")]
+    ManifestError(std::io::Error),
+    #[error("error during IO")]
+    IOError(#[from] std::io::Error),
+    #[error("failed to parse VDF: {0}")]
+    VdfError(#[from] vdf::ParseError),
+    #[error("unexpected VDF content: {0}")]
+    VdfDeError(#[from] vdf::DeError),
+    #[error("no app info in the output of SteamCMD")]
+    AppInfoMissing,
+    #[error("SteamCMD job {id} failed")]
+    JobFailed {
+        id: job::JobId,
+        result: job::JobResult,
+    },
+    #[error("branch `{0}` not found in the app info")]
+    BranchMissing(String),
+    #[error("not enough disk space, {required} bytes needed but {free} are free")]
+    InsufficientSpace { required: u64, free: u64 },
+}
+
+impl IntoResponse for SteamCMDError {
+    fn into_response(self) -> Response {
+        match self {
+            SteamCMDError::Busy(job_id) => (
+                StatusCode::CONFLICT,
+                Json(json!({ "message": self.to_string(), "job_id": job_id })),
+            )
+                .into_response(),
+            SteamCMDError::JobFailed { id, ref result } => {
+                let mut body = json!(result);
+                body["message"] = json!(self.to_string());
+                body["job_id"] = json!(id);
+                (StatusCode::BAD_GATEWAY, Json(body)).into_response()
+            }
+            SteamCMDError::InsufficientSpace { required, free } => (
+                StatusCode::INSUFFICIENT_STORAGE,
+                Json(json!({ "message": self.to_string(), "required": required, "free": free })),
+            )
+                .into_response(),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {:?}", self),
+            )
+                .into_response(),
+        }
+    }
 }
 
 type SteamCMDResult<T> = Result<T, SteamCMDError>;
 
-pub async fn run_steamcmd(
+/// SteamCMD reads prompted input from the returned stdin, dropping it answers them with EOF.
+pub fn run_steamcmd(
+    paths: &Paths,
     args: impl IntoIterator<Item = impl AsRef<OsStr>>,
-) -> SteamCMDResult<(Child, ReaderStream<ChildStdout>)> {
-    let mut child = Command::new("/bin/stdbuf")
-        .arg("--output=0")
-        .arg(STEAMCMD_EXE)
+) -> SteamCMDResult<(Child, ChildStdin, ReaderStream<ChildStdout>)> {
+    let mut command = match &paths.stdbuf {
+        Some(stdbuf) => {
+            let mut command = Command::new(stdbuf);
+            command.arg("--output=0").arg(&paths.steamcmd);
+            command
+        }
+        None => Command::new(&paths.steamcmd),
+    };
+    let mut child = command
         .args(args)
+        .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        // so signals reach steamcmd.sh's children too, see `signal_group`
+        .process_group(0)
         .spawn()
         .map_err(SteamCMDError::SpawnError)?;
 
-    drop(child.stdin.take());
-
+    let stdin = child.stdin.take().unwrap();
     let stdout = ReaderStream::new(child.stdout.take().unwrap());
 
-    Ok((child, stdout))
+    Ok((child, stdin, stdout))
+}
+
+/// Sends `signal` to the process group of a child spawned by [`run_steamcmd`].
+pub fn signal_group(child: &Child, signal: Signal) -> nix::Result<()> {
+    match child.id() {
+        Some(pid) => killpg(Pid::from_raw(pid as i32), signal),
+        // already exited and reaped
+        None => Ok(()),
+    }
+}
+
+const APP_ID: &str = "2394010";
+
+/// Records the depot installed by the last [`UpdateType::Depot`] job, until the next
+/// [`UpdateType::Game`] job.
+fn pin_file(paths: &Paths) -> PathBuf {
+    paths.data_dir.join("pinned_depot.json")
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateCheck {
+    /// The installed branch, compared with the same branch in the app info.
+    pub branch: String,
+    /// `None` if the game is not installed.
+    pub installed_build_id: Option<String>,
+    pub latest_build_id: String,
+    /// The branch needs a password, which SteamCMD is not given without a client asking.
+    pub password_required: bool,
+    /// Set while a specific build is installed, which is never considered outdated.
+    pub pinned: Option<PinnedDepot>,
+    pub update_available: bool,
+}
+
+/// Compares the installed build with the latest one in the output of an
+/// [`UpdateType::AppInfo`] job.
+pub async fn update_check(paths: &Paths, app_info_output: &str) -> SteamCMDResult<UpdateCheck> {
+    let installed = read_app_manifest(paths).await?;
+    let branch = installed
+        .as_ref()
+        .and_then(|app| app.user_config.betakey.clone())
+        .filter(|branch| !branch.is_empty())
+        .unwrap_or_else(|| "public".to_string());
+    let latest = parse_app_info(app_info_output)?
+        .depots
+        .branches
+        .remove(&branch)
+        .ok_or_else(|| SteamCMDError::BranchMissing(branch.clone()))?;
+    let latest_build_id = latest.buildid;
+    let installed_build_id = installed.map(|app| app.buildid);
+    let pinned = pinned_depot(paths).await?;
+    Ok(UpdateCheck {
+        branch,
+        update_available: pinned.is_none() && installed_build_id.as_ref() != Some(&latest_build_id),
+        installed_build_id,
+        latest_build_id,
+        password_required: latest.pwdrequired,
+        pinned,
+    })
+}
+
+/// A depot build installed by an [`UpdateType::Depot`] job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedDepot {
+    pub depot: u64,
+    pub manifest: u64,
+}
+
+pub async fn pinned_depot(paths: &Paths) -> SteamCMDResult<Option<PinnedDepot>> {
+    match tokio::fs::read(pin_file(paths)).await {
+        Ok(pin) => Ok(Some(
+            serde_json::from_slice(&pin).map_err(std::io::Error::from)?,
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Bookkeeping after SteamCMD succeeded, e.g. moving a downloaded depot into place.
+async fn after_success(paths: &Paths, update_type: &UpdateType) -> std::io::Result<()> {
+    let pin_file = pin_file(paths);
+    match update_type {
+        UpdateType::Depot { depot, manifest } => {
+            let install_dir = paths.install_dir.clone();
+            let content =
+                install_dir.join(format!("steamapps/content/app_{}/depot_{}", APP_ID, depot));
+            tokio::task::spawn_blocking(move || {
+                copy_dir(&content, &install_dir)?;
+                std::fs::remove_dir_all(&content)
+            })
+            .await??;
+            let pin = PinnedDepot {
+                depot: *depot,
+                manifest: *manifest,
+            };
+            tokio::fs::create_dir_all(&paths.data_dir).await?;
+            tokio::fs::write(pin_file, serde_json::to_vec(&pin)?).await
+        }
+        UpdateType::Game { .. } => match tokio::fs::remove_file(pin_file).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        },
+        UpdateType::Login { username, .. } => {
+            let account = Account {
+                username: username.clone(),
+            };
+            let paths = paths.clone();
+            tokio::task::spawn_blocking(move || account::write_account(&paths, &account)).await?
+        }
+        UpdateType::Steam | UpdateType::AppInfo => Ok(()),
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// The parts of `appmanifest_2394010.acf` in use, written by SteamCMD after installing.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppManifest {
+    #[serde(rename = "AppState")]
+    pub app_state: AppState,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppState {
+    pub buildid: String,
+    #[serde(rename = "SizeOnDisk", default)]
+    pub size_on_disk: u64,
+    /// See [`disk::InstallState`].
+    #[serde(rename = "StateFlags", default)]
+    pub state_flags: u32,
+    #[serde(rename = "InstalledDepots", default)]
+    pub installed_depots: BTreeMap<String, InstalledDepot>,
+    #[serde(rename = "UserConfig", default)]
+    pub user_config: UserConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InstalledDepot {
+    pub manifest: String,
+    #[serde(default)]
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserConfig {
+    /// The branch, `None` for `public`.
+    pub betakey: Option<String>,
+}
+
+/// The parts of `app_info_print` in use.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppInfo {
+    pub depots: Depots,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Depots {
+    #[serde(default)]
+    pub branches: BTreeMap<String, Branch>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Branch {
+    pub buildid: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub pwdrequired: bool,
+    /// Unix seconds.
+    pub timeupdated: Option<u64>,
+}
+
+/// `None` if the game is not installed.
+pub async fn read_app_manifest(paths: &Paths) -> SteamCMDResult<Option<AppState>> {
+    let manifest = match tokio::fs::read_to_string(paths.app_manifest(APP_ID)).await {
+        Ok(manifest) => manifest,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(SteamCMDError::ManifestError(err)),
+    };
+    let manifest: AppManifest = vdf::from_value(&vdf::parse(&manifest)?)?;
+    Ok(Some(manifest.app_state))
+}
+
+/// Finds the app info among the rest of the output of an [`UpdateType::AppInfo`] job.
+pub fn parse_app_info(output: &str) -> SteamCMDResult<AppInfo> {
+    Ok(vdf::from_value(&app_info_value(output)?)?)
+}
+
+/// Like [`parse_app_info`], keeping everything.
+fn app_info_value(output: &str) -> SteamCMDResult<vdf::Value> {
+    // printed as `"2394010" { ... }`
+    let key = format!("\"{}\"", APP_ID);
+    let start = output
+        .match_indices(&key)
+        .map(|(i, _)| i)
+        .find(|&i| output[i + key.len()..].trim_start().starts_with('{'))
+        .ok_or(SteamCMDError::AppInfoMissing)?;
+    let (_, info) = vdf::parse_first(&output[start..])?;
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_args(branch: Option<&str>, password: Option<&str>) -> Vec<String> {
+        let update_type = UpdateType::Game {
+            validate: false,
+            branch: branch.map(str::to_string),
+            branch_password: password.map(|p| serde_json::from_value(p.into()).unwrap()),
+        };
+        update_args_for(&Paths::new("/srv/pal"), None, &update_type)
+    }
+
+    #[test]
+    fn branch_password_only_with_branch() {
+        let args = game_args(Some("beta"), Some("hunter2"));
+        assert!(args
+            .windows(4)
+            .any(|w| w == ["-beta", "beta", "-betapassword", "hunter2"]));
+        let args = game_args(None, Some("hunter2"));
+        assert!(!args
+            .iter()
+            .any(|arg| arg == "-betapassword" || arg == "hunter2"));
+    }
+
+    #[test]
+    fn does_only_the_same_update() {
+        let game = |validate, branch: Option<&str>| UpdateType::Game {
+            validate,
+            branch: branch.map(str::to_string),
+            branch_password: None,
+        };
+        assert!(game(true, None).does(&game(true, None)));
+        assert!(!game(true, None).does(&game(false, None)));
+        assert!(!game(true, Some("beta")).does(&game(true, None)));
+        assert!(!UpdateType::AppInfo.does(&game(true, None)));
+        assert!(!UpdateType::Steam.does(&game(true, None)));
+        let depot = |manifest| UpdateType::Depot {
+            depot: 2394012,
+            manifest,
+        };
+        assert!(depot(1).does(&depot(1)));
+        assert!(!depot(1).does(&depot(2)));
+        let login = UpdateType::Login {
+            username: "steam".to_string(),
+            password: None,
+        };
+        assert!(!login.does(&login.clone()));
+    }
 }
@@ -0,0 +1,424 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::Serialize;
+
+use super::job::JobResult;
+
+static SELF_UPDATE_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[(?<progress>....)\] (?<status>.+)$").unwrap());
+static UPDATE_STATE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^ Update state \(0x(?<state_id>[\da-f]+)\) (?<state_name>[\w ]+), progress: (?<progress>\d*\.\d*) \((?<current>\d+) / (?<total>\d+)\)$").unwrap()
+});
+/// Not anchored, it may follow a prompt on the same line.
+static LOGGED_IN_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?:Connecting anonymously|Logging in user '(?<user>[^']+)'.*) to Steam Public\.\.\.OK$",
+    )
+    .unwrap()
+});
+/// Logging in, or a step of it, failed.
+static FAILED_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\.\.\.FAILED \((?<reason>.+)\)$").unwrap());
+static ERROR_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^ERROR! (?<message>.+?)(?: \((?<reason>.+)\))?\.?$").unwrap());
+/// Printed instead of an `ERROR!` for some failures, e.g. `0x202` when out of disk space.
+static APP_STATE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^Error! App '\d+' state is 0x(?<state>[\da-f]+) after update job\.$").unwrap()
+});
+
+/// App state flags SteamCMD ends up with when the disk is full.
+const APP_STATE_DISK_FULL: u32 = 0x202;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UpdateSteamMessage {
+    SteamSelfUpdate {
+        status: String,
+        /// Percent, `None` while SteamCMD shows `[----]`.
+        progress: Option<f64>,
+    },
+    LoggedIn {
+        /// `None` when logged in anonymously.
+        user: Option<String>,
+    },
+    /// SteamCMD waits for the user, clients answer with an `input` message.
+    Prompt {
+        prompt: Prompt,
+    },
+    UpdateState {
+        state_id: u32,
+        state_name: String,
+        stage: Stage,
+        /// Percent.
+        progress: f64,
+        current: u64,
+        total: u64,
+    },
+    Success,
+    Error {
+        reason: String,
+        kind: ErrorKind,
+    },
+    Cancelled,
+    /// From the gateway rather than SteamCMD, sent before the job's messages.
+    Warning {
+        reason: String,
+    },
+    /// Always the last message.
+    Finished {
+        #[serde(flatten)]
+        result: JobResult,
+    },
+}
+
+/// What [`UpdateSteamMessage::UpdateState`] is doing, as far as it is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Reconfiguring,
+    Preallocating,
+    Downloading,
+    Verifying,
+    Committing,
+    Other,
+}
+
+impl Stage {
+    fn from_state_name(name: &str) -> Stage {
+        match name.split(' ').next().unwrap_or_default() {
+            "reconfiguring" => Stage::Reconfiguring,
+            "preallocating" => Stage::Preallocating,
+            "downloading" => Stage::Downloading,
+            "verifying" => Stage::Verifying,
+            "committing" => Stage::Committing,
+            _ => Stage::Other,
+        }
+    }
+}
+
+/// What SteamCMD waits for while logging in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Prompt {
+    Password,
+    /// Mailed to the account's address.
+    SteamGuardCode,
+    /// From the Steam Mobile app.
+    TwoFactorCode,
+    /// Nothing to type, SteamCMD continues once the login is confirmed in the Steam Mobile app.
+    MobileConfirmation,
+}
+
+/// Recognizes a prompt from a line SteamCMD has not finished, it waits for input after it.
+pub fn parse_prompt(partial_line: &str) -> Option<Prompt> {
+    let line = partial_line.trim_end();
+    if line.ends_with("password:") {
+        Some(Prompt::Password)
+    } else if line.ends_with("Steam Guard code:") {
+        Some(Prompt::SteamGuardCode)
+    } else if line.ends_with("Two-factor code:") {
+        Some(Prompt::TwoFactorCode)
+    } else {
+        None
+    }
+}
+
+/// Known failures that clients may explain better than SteamCMD does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Usually fixed by running the update again.
+    MissingConfiguration,
+    DiskSpace,
+    RateLimited,
+    LoginFailed,
+    Other,
+}
+
+impl ErrorKind {
+    fn from_reason(reason: &str) -> ErrorKind {
+        let reason = reason.to_lowercase();
+        if reason.contains("missing configuration") {
+            ErrorKind::MissingConfiguration
+        } else if reason.contains("disk") {
+            ErrorKind::DiskSpace
+        } else if reason.contains("rate limit") {
+            ErrorKind::RateLimited
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// Recognizes a line of SteamCMD's output, without its line ending.
+pub fn parse_line(line: &str) -> Option<UpdateSteamMessage> {
+    if line.starts_with("Success!") || line.starts_with("Depot download complete") {
+        return Some(UpdateSteamMessage::Success);
+    }
+
+    if let Some(cap) = UPDATE_STATE_PATTERN.captures(line) {
+        let state_name = cap["state_name"].to_string();
+        return Some(UpdateSteamMessage::UpdateState {
+            state_id: u32::from_str_radix(&cap["state_id"], 16).ok()?,
+            stage: Stage::from_state_name(&state_name),
+            state_name,
+            progress: cap["progress"].parse().ok()?,
+            current: cap["current"].parse().ok()?,
+            total: cap["total"].parse().ok()?,
+        });
+    }
+
+    if let Some(cap) = SELF_UPDATE_PATTERN.captures(line) {
+        let progress = cap["progress"].trim().strip_suffix('%');
+        return Some(UpdateSteamMessage::SteamSelfUpdate {
+            status: cap["status"].to_string(),
+            progress: progress.and_then(|p| p.parse().ok()),
+        });
+    }
+
+    if line.starts_with("Please confirm the login in the Steam Mobile app") {
+        return Some(UpdateSteamMessage::Prompt {
+            prompt: Prompt::MobileConfirmation,
+        });
+    }
+
+    if let Some(cap) = LOGGED_IN_PATTERN.captures(line) {
+        return Some(UpdateSteamMessage::LoggedIn {
+            user: cap.name("user").map(|user| user.as_str().to_string()),
+        });
+    }
+
+    if let Some(cap) = FAILED_PATTERN.captures(line) {
+        let reason = cap["reason"].to_string();
+        let kind = match ErrorKind::from_reason(&reason) {
+            // may follow a prompt on the same line
+            ErrorKind::Other if line.contains("Logging in") => ErrorKind::LoginFailed,
+            kind => kind,
+        };
+        return Some(UpdateSteamMessage::Error { reason, kind });
+    }
+
+    if let Some(cap) = ERROR_PATTERN.captures(line) {
+        let reason = cap.name("reason").unwrap_or(cap.name("message")?).as_str();
+        return Some(UpdateSteamMessage::Error {
+            reason: reason.to_string(),
+            kind: ErrorKind::from_reason(reason),
+        });
+    }
+
+    if let Some(cap) = APP_STATE_PATTERN.captures(line) {
+        let state = u32::from_str_radix(&cap["state"], 16).ok()?;
+        return Some(UpdateSteamMessage::Error {
+            reason: format!("app state is 0x{:x} after updating", state),
+            kind: if state == APP_STATE_DISK_FULL {
+                ErrorKind::DiskSpace
+            } else {
+                ErrorKind::Other
+            },
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(log: &str) -> Vec<UpdateSteamMessage> {
+        log.lines().filter_map(parse_line).collect()
+    }
+
+    fn prompts(log: &str) -> Vec<Prompt> {
+        log.lines().filter_map(parse_prompt).collect()
+    }
+
+    fn self_update(status: &str, progress: Option<f64>) -> UpdateSteamMessage {
+        UpdateSteamMessage::SteamSelfUpdate {
+            status: status.to_string(),
+            progress,
+        }
+    }
+
+    fn logged_in(user: Option<&str>) -> UpdateSteamMessage {
+        UpdateSteamMessage::LoggedIn {
+            user: user.map(str::to_string),
+        }
+    }
+
+    fn error(reason: &str, kind: ErrorKind) -> UpdateSteamMessage {
+        UpdateSteamMessage::Error {
+            reason: reason.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn self_update_log() {
+        let log = include_str!("../../tests/fixtures/steamcmd/self_update.log");
+        assert_eq!(
+            messages(log),
+            [
+                self_update("Checking for available updates...", Some(0.0)),
+                self_update("Downloading update (0 of 58,328 KB)...", None),
+                self_update("Downloading update (1,048 of 58,328 KB)...", Some(0.0)),
+                self_update("Downloading update (27,551 of 58,328 KB)...", Some(47.0)),
+                self_update("Downloading update (58,328 of 58,328 KB)...", Some(100.0)),
+                self_update("Download Complete.", Some(100.0)),
+                self_update("Applying update...", None),
+                self_update("Extracting package...", None),
+                self_update("Installing update...", None),
+                self_update("Cleaning up...", None),
+                self_update("Update complete, launching Steamcmd...", None),
+                self_update("Checking for available updates...", Some(0.0)),
+                self_update("Verifying installation...", None),
+                logged_in(None),
+            ]
+        );
+    }
+
+    #[test]
+    fn app_update_log() {
+        let log = include_str!("../../tests/fixtures/steamcmd/app_update.log");
+        let states: Vec<_> = messages(log)
+            .into_iter()
+            .filter_map(|msg| match msg {
+                UpdateSteamMessage::UpdateState {
+                    state_id,
+                    stage,
+                    progress,
+                    current,
+                    total,
+                    ..
+                } => Some((state_id, stage, progress, current, total)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (0x3, Stage::Reconfiguring, 0.0, 0, 0),
+                (0x11, Stage::Preallocating, 32.5, 975123456, 3000456789),
+                (0x61, Stage::Downloading, 12.34, 370256384, 3000456789),
+                (0x61, Stage::Downloading, 99.87, 2996556789, 3000456789),
+                (0x81, Stage::Verifying, 48.02, 1440819234, 3000456789),
+                (0x101, Stage::Committing, 100.0, 3000456789, 3000456789),
+            ]
+        );
+        let messages = messages(log);
+        assert!(messages.contains(&logged_in(None)));
+        assert_eq!(messages.last(), Some(&UpdateSteamMessage::Success));
+        assert_eq!(
+            messages[messages.len() - 3],
+            UpdateSteamMessage::UpdateState {
+                state_id: 0x81,
+                state_name: "verifying update".to_string(),
+                stage: Stage::Verifying,
+                progress: 48.02,
+                current: 1440819234,
+                total: 3000456789,
+            }
+        );
+    }
+
+    #[test]
+    fn login_with_steam_guard() {
+        let log = include_str!("../../tests/fixtures/steamcmd/login_guard.log");
+        assert_eq!(prompts(log), [Prompt::Password, Prompt::SteamGuardCode]);
+        assert_eq!(messages(log), [logged_in(Some("palboard"))]);
+    }
+
+    #[test]
+    fn login_with_mobile_confirmation() {
+        let log = include_str!("../../tests/fixtures/steamcmd/login_mobile.log");
+        assert_eq!(prompts(log), []);
+        assert_eq!(
+            messages(log),
+            [
+                UpdateSteamMessage::Prompt {
+                    prompt: Prompt::MobileConfirmation
+                },
+                logged_in(Some("palboard")),
+            ]
+        );
+    }
+
+    #[test]
+    fn two_factor_prompt() {
+        assert_eq!(
+            parse_prompt("Two-factor code:"),
+            Some(Prompt::TwoFactorCode)
+        );
+        // not a prompt until the line ends with it
+        assert_eq!(parse_prompt("Steam Guard code: 12"), None);
+    }
+
+    #[test]
+    fn login_failed() {
+        let log = include_str!("../../tests/fixtures/steamcmd/login_failed.log");
+        // the failure follows the prompt on the same line, which is partial until then
+        let line = log
+            .lines()
+            .find(|line| line.starts_with("password:"))
+            .unwrap();
+        let partial = &line[..line.find("Logging in").unwrap()];
+        assert_eq!(parse_prompt(partial), Some(Prompt::Password));
+        assert_eq!(
+            messages(log),
+            [error("Invalid Password", ErrorKind::LoginFailed)]
+        );
+    }
+
+    #[test]
+    fn login_failed_on_its_own_line() {
+        // the fixtures guess where SteamCMD breaks lines around prompts, either way parses alike
+        let log = "password: \nLogging in user 'palboard' [U:1:0] to Steam Public...FAILED (Invalid Password)\n";
+        assert_eq!(prompts(log), [Prompt::Password]);
+        assert_eq!(
+            messages(log),
+            [error("Invalid Password", ErrorKind::LoginFailed)]
+        );
+        assert_eq!(
+            messages("Connecting anonymously to Steam Public...FAILED (No Connection)"),
+            [error("No Connection", ErrorKind::Other)]
+        );
+    }
+
+    #[test]
+    fn rate_limited() {
+        let log = include_str!("../../tests/fixtures/steamcmd/rate_limited.log");
+        assert_eq!(
+            messages(log),
+            [error("Rate Limit Exceeded", ErrorKind::RateLimited)]
+        );
+    }
+
+    #[test]
+    fn missing_configuration() {
+        let log = include_str!("../../tests/fixtures/steamcmd/missing_configuration.log");
+        assert_eq!(
+            messages(log).last(),
+            Some(&error(
+                "Missing configuration",
+                ErrorKind::MissingConfiguration
+            ))
+        );
+    }
+
+    #[test]
+    fn disk_full() {
+        let log = include_str!("../../tests/fixtures/steamcmd/disk_full.log");
+        assert_eq!(
+            messages(log).last(),
+            Some(&error(
+                "app state is 0x202 after updating",
+                ErrorKind::DiskSpace
+            ))
+        );
+        assert_eq!(
+            parse_line("ERROR! Failed to install app '2394010' (Disk write failure)"),
+            Some(error("Disk write failure", ErrorKind::DiskSpace))
+        );
+    }
+}
@@ -1,176 +1,492 @@
 use axum::{
-    extract::{ws::WebSocket, Query, WebSocketUpgrade},
-    response::Response,
-    routing::get,
-    Router,
-};
-use serde::{Deserialize, Serialize};
-
-use tokio::{
-    io::AsyncBufReadExt,
-    spawn,
-    sync::{mpsc, Mutex},
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        Path, Query, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
 };
-use tokio_stream::{wrappers::ReceiverStream, StreamExt};
-use tokio_util::io::StreamReader;
-use tracing::{debug, debug_span, error, instrument, Instrument};
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, instrument, warn};
 
 use std::sync::Arc;
 
-use crate::steamcmd::BUFFER_SIZE;
+use crate::AppResult;
 
-use super::{run_steamcmd, update_args_for, UpdateType};
+use super::{
+    account::{read_account, remove_account, Account, Secret},
+    disk::{disk_report, DiskReport},
+    job::{Job, JobEvent, JobId, JobInfo, JobManager, JobResult, JobStatus},
+    parser::ErrorKind,
+    update_check, SteamCMDError, SteamCMDResult, UpdateCheck, UpdateSteamMessage, UpdateType,
+};
 
-pub fn new_router() -> Router<()> {
-    Router::new().route("/update", get(update_steam_handler))
+pub fn new_router(jobs: JobManager) -> Router<()> {
+    Router::new()
+        .route("/update", get(update_steam_handler))
+        .route("/update/check", get(update_check_handler))
+        .route("/disk", get(disk_handler))
+        .route("/jobs", get(jobs_handler))
+        .route("/jobs/:id", get(job_handler))
+        .route("/jobs/:id/attach", get(attach_handler))
+        .route("/jobs/:id/cancel", post(cancel_handler))
+        .route("/account", get(account_handler).delete(logout_handler))
+        .route("/login", get(login_handler))
+        .with_state(jobs)
 }
 
 #[derive(Debug, Deserialize)]
 struct UpdateSteamQuery {
     game: Option<bool>,
     validate: Option<bool>,
+    branch: Option<String>,
+    /// The branch needs a password, the client sends it in a `branch_password` message first so
+    /// it stays out of the URL.
+    branch_password: Option<bool>,
+    /// Installs this depot build instead, `manifest` is required along with it.
+    depot: Option<u64>,
+    manifest: Option<u64>,
+    /// Attach to the running job instead of failing if SteamCMD is busy with the same update,
+    /// see [`UpdateType::does`].
+    attach: Option<bool>,
+    /// Update even if the disk seems too full, see [`DiskReport::check`].
+    force: Option<bool>,
 }
+
+/// Starts a job and attaches to it, the job keeps running if the socket closes.
 async fn update_steam_handler(
+    State(jobs): State<JobManager>,
     ws: WebSocketUpgrade,
     Query(q): Query<UpdateSteamQuery>,
-) -> Response {
-    let update_type = if q.game.unwrap_or(false) {
-        UpdateType::Game {
+) -> AppResult<Response> {
+    let update_type = match (q.depot, q.manifest) {
+        (Some(depot), Some(manifest)) => UpdateType::Depot { depot, manifest },
+        (None, None) if q.game.unwrap_or(false) => UpdateType::Game {
             validate: q.validate.unwrap_or(true),
+            branch: q.branch,
+            branch_password: None,
+        },
+        (None, None) => UpdateType::Steam,
+        _ => {
+            return Ok((
+                StatusCode::BAD_REQUEST,
+                "`depot` and `manifest` go together",
+            )
+                .into_response())
         }
-    } else {
-        UpdateType::Steam
     };
 
-    ws.on_upgrade(|ws| update_steam(ws, update_type))
-}
+    // SteamCMD ignores `-betapassword` without `-beta`
+    let with_password = q.branch_password.unwrap_or(false);
+    if with_password
+        && !matches!(
+            update_type,
+            UpdateType::Game {
+                branch: Some(_),
+                ..
+            }
+        )
+    {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            "`branch_password` needs `game` and `branch`",
+        )
+            .into_response());
+    }
 
-#[derive(Debug, Serialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum UpdateSteamMessage {
-    SteamSelfUpdate {
-        status: String,
-    },
-    UpdateState {
-        state_id: u32,
-        state_name: String,
-        progress: String,
-        current: u64,
-        total: u64,
-    },
-    Success,
-    Error {
-        reason: String,
-    },
+    let attach_running = q.attach.unwrap_or(false);
+    let force = q.force.unwrap_or(false);
+    Ok(
+        ws.on_upgrade(move |ws| {
+            update(ws, jobs, update_type, with_password, attach_running, force)
+        }),
+    )
 }
 
-fn parse_line(line: &str) -> Option<UpdateSteamMessage> {
-    // TODO: reusing regexes
-    let update_state_pattern = regex::Regex::new(r"^ Update state \(0x(?<state_id>[\da-f]+)\) (?<state_name>[\w ]+), progress: (?<progress>\d*\.\d*) \((?<current>\d+) \/ (?<total>\d+)\)$").unwrap();
-    let steam_self_update_pattern = regex::Regex::new(r"^\[....\] (.+)$").unwrap();
-    let error_pattern = regex::Regex::new(r"^ERROR!.+\((.+)\)$").unwrap();
-
-    if line.starts_with("Success!") {
-        return Some(UpdateSteamMessage::Success);
+/// Expects a `branch_password` message first if `with_password`, like [`login`]. Then checks the
+/// disk space unless `force`d or following a running update, and starts the update.
+#[instrument(skip_all)]
+async fn update(
+    mut ws: WebSocket,
+    jobs: JobManager,
+    mut update_type: UpdateType,
+    with_password: bool,
+    attach_running: bool,
+    force: bool,
+) {
+    if with_password {
+        let password = match ws.recv().await {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                Ok(ClientMessage::BranchPassword { password }) => password,
+                _ => {
+                    let reason = "expected a branch_password message";
+                    return close(ws, close_code::POLICY, reason.to_string()).await;
+                }
+            },
+            _ => {
+                debug!("client left before sending the branch password");
+                return;
+            }
+        };
+        if let UpdateType::Game {
+            branch_password, ..
+        } = &mut update_type
+        {
+            *branch_password = Some(password);
+        }
     }
 
-    if let Some(cap) = steam_self_update_pattern.captures(&line) {
-        let (_, [status]) = cap.extract();
-        let status = status.to_string();
-        return Some(UpdateSteamMessage::SteamSelfUpdate { status });
+    // checked when it started
+    let running = jobs
+        .running()
+        .filter(|job| attach_running && job.update_type.does(&update_type));
+    if let (None, false) = (&running, force) {
+        match check_disk_space(&mut ws, &jobs, &update_type).await {
+            Ok(Some(reason)) => {
+                let warning = UpdateSteamMessage::Warning { reason };
+                if !send(&mut ws, warning).await {
+                    debug!("client left before the update started");
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                let reason = err.to_string();
+                let kind = match err {
+                    SteamCMDError::InsufficientSpace { .. } => ErrorKind::DiskSpace,
+                    _ => ErrorKind::Other,
+                };
+                warn!("refusing the update: {}", reason);
+                let error = UpdateSteamMessage::Error {
+                    reason: reason.clone(),
+                    kind,
+                };
+                send(&mut ws, error).await;
+                return close(ws, close_code::ERROR, reason).await;
+            }
+        }
     }
 
-    if let Some(cap) = error_pattern.captures(&line) {
-        let (_, [reason]) = cap.extract();
-        let reason = reason.to_string();
-        return Some(UpdateSteamMessage::Error { reason });
+    let job = match running {
+        Some(job) => Ok(job),
+        None => start_or_attach(&jobs, update_type, attach_running),
+    };
+    match job {
+        Ok(job) => attach(ws, job).await,
+        Err(err @ SteamCMDError::Busy(_)) => close(ws, close_code::AGAIN, err.to_string()).await,
+        Err(err) => {
+            warn!("failed to start the update job: {:?}", err);
+            close(ws, close_code::ERROR, err.to_string()).await
+        }
     }
+}
 
-    if let Some(cap) = update_state_pattern.captures(&line) {
-        let (_, [state_id, state_name, progress, current, total]) = cap.extract();
-        let state_id = u32::from_str_radix(state_id, 16).unwrap();
-        let current = u64::from_str_radix(current, 10).unwrap();
-        let total = u64::from_str_radix(total, 10).unwrap();
-        let state_name = state_name.to_string();
-        let progress = progress.to_string();
-        return Some(UpdateSteamMessage::UpdateState {
-            state_id,
-            state_name,
-            progress,
-            current,
-            total,
-        });
+fn start_or_attach(
+    jobs: &JobManager,
+    update_type: UpdateType,
+    attach_running: bool,
+) -> Result<Arc<Job>, SteamCMDError> {
+    match jobs.start(update_type.clone()) {
+        Err(SteamCMDError::Busy(_)) if attach_running => match jobs.running() {
+            Some(job) if job.update_type.does(&update_type) => Ok(job),
+            Some(job) => Err(SteamCMDError::Busy(job.id)),
+            // finished in the meantime
+            None => jobs.start(update_type),
+        },
+        res => res,
     }
+}
 
-    None
+/// Fetches the latest app info as a job, so it gets a 409 like any other while SteamCMD is busy.
+async fn update_check_handler(State(jobs): State<JobManager>) -> AppResult<Json<UpdateCheck>> {
+    let output = jobs.start(UpdateType::AppInfo)?.successful_output().await?;
+    Ok(Json(update_check(jobs.paths(), &output).await?))
 }
 
-#[instrument(skip_all)]
-pub async fn update_steam(ws: WebSocket, update_type: UpdateType) {
-    let (mut child, mut stdout) = run_steamcmd(update_args_for(update_type)).await.unwrap();
-
-    let (tx, rx) = mpsc::channel(BUFFER_SIZE);
-
-    let rx = ReceiverStream::new(rx);
-    let mut lines = StreamReader::new(rx).lines();
-
-    let ws = Arc::new(Mutex::new(ws));
-    let ws_lr = ws.clone();
-
-    let line_reader = spawn(
-        async move {
-            while let Some(line) = lines.next_line().await.unwrap() {
-                debug!("parsing line: {}", line);
-
-                if let Some(msg) = parse_line(&line) {
-                    ws_lr
-                        .lock()
-                        .await
-                        .send(axum::extract::ws::Message::Text(
-                            serde_json::to_string(&msg).unwrap(),
-                        ))
-                        .await
-                        .unwrap();
+/// Refuses updates that would most likely fill the disk, comparing with the app info of the
+/// latest [`update_check_handler`] call. Without one it is fetched, and the client follows the
+/// job. Returns a warning for the client if the space could not be checked.
+async fn check_disk_space(
+    ws: &mut WebSocket,
+    jobs: &JobManager,
+    update_type: &UpdateType,
+) -> SteamCMDResult<Option<String>> {
+    let branch = match update_type {
+        UpdateType::Game { branch, .. } => branch.as_deref(),
+        UpdateType::Depot { .. } => None,
+        _ => return Ok(None),
+    };
+    let app_info = match jobs.last_app_info() {
+        Some(app_info) => app_info,
+        None => match jobs.start(UpdateType::AppInfo) {
+            Ok(job) => {
+                forward(ws, &job).await;
+                match job.successful_output().await {
+                    Ok(app_info) => app_info,
+                    Err(err) => {
+                        warn!("failed to fetch the app info: {}", err);
+                        let warning =
+                            "failed to fetch the app info, the disk space was not checked";
+                        return Ok(Some(warning.to_string()));
+                    }
                 }
             }
-            debug!("exit");
+            // the update is refused anyway
+            Err(SteamCMDError::Busy(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        },
+    };
+    let report = disk_report(jobs.paths(), Some(&app_info), branch).await?;
+    if report.required_for(update_type).is_none() {
+        let warning = "the app info lists no download size, the disk space was not checked";
+        warn!("{}", warning);
+        return Ok(Some(warning.to_string()));
+    }
+    report.check(update_type)?;
+    Ok(None)
+}
+
+/// Sends the job's output and messages to the client until it finishes, but not the result, so
+/// the client sees what the update waits for.
+async fn forward(ws: &mut WebSocket, job: &Job) {
+    let (replay, rx) = job.subscribe();
+    let replay = replay
+        .into_iter()
+        .filter(|event| !matches!(event, JobEvent::Finished(_)));
+    for msg in replay.filter_map(to_message) {
+        let _ = ws.send(msg).await;
+    }
+    let Some(mut rx) = rx else { return };
+    loop {
+        match rx.recv().await {
+            Ok(JobEvent::Finished(_)) | Err(RecvError::Closed) => return,
+            Ok(event) => {
+                if let Some(msg) = to_message(event) {
+                    let _ = ws.send(msg).await;
+                }
+            }
+            Err(RecvError::Lagged(n)) => warn!("client skipped {} events", n),
         }
-        .instrument(debug_span!("line_reader")),
-    );
-
-    while let Some(chunk) = stdout.next().await {
-        match chunk {
-            Ok(data) => {
-                tx.send(Ok(data.clone())).await.unwrap();
-                debug!("piping data to user: {:?}", data);
-                let res = ws
-                    .lock()
-                    .await
-                    .send(axum::extract::ws::Message::Binary(data.to_vec()))
-                    .await;
-                debug!("pipe result: {:?}", res);
+    }
+}
+
+/// Sends a message from the gateway itself, `false` if the client left.
+async fn send(ws: &mut WebSocket, msg: UpdateSteamMessage) -> bool {
+    match to_message(JobEvent::Message(msg)) {
+        Some(msg) => ws.send(msg).await.is_ok(),
+        None => true,
+    }
+}
+
+/// Fetches the latest app info like [`update_check_handler`], while SteamCMD is busy the report
+/// uses the last one fetched, if any.
+async fn disk_handler(State(jobs): State<JobManager>) -> AppResult<Json<DiskReport>> {
+    let app_info = match jobs.start(UpdateType::AppInfo) {
+        Ok(job) => Some(job.successful_output().await?),
+        Err(SteamCMDError::Busy(_)) => jobs.last_app_info(),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(Json(
+        disk_report(jobs.paths(), app_info.as_deref(), None).await?,
+    ))
+}
+
+async fn jobs_handler(State(jobs): State<JobManager>) -> Json<Vec<JobInfo>> {
+    Json(jobs.list().iter().map(|job| job.info()).collect())
+}
+
+async fn job_handler(State(jobs): State<JobManager>, Path(id): Path<JobId>) -> Response {
+    match jobs.get(id) {
+        Some(job) => Json(job.info()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Replays everything the job has output so far, then follows it.
+async fn attach_handler(
+    State(jobs): State<JobManager>,
+    ws: WebSocketUpgrade,
+    Path(id): Path<JobId>,
+) -> Response {
+    match jobs.get(id) {
+        Some(job) => ws.on_upgrade(|ws| attach(ws, job)),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn cancel_handler(State(jobs): State<JobManager>, Path(id): Path<JobId>) -> Response {
+    match jobs.get(id) {
+        Some(job) if job.cancel() => StatusCode::ACCEPTED.into_response(),
+        Some(_) => (StatusCode::CONFLICT, "the job has finished already").into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// `null` while logging in anonymously.
+async fn account_handler(State(jobs): State<JobManager>) -> AppResult<Json<Option<Account>>> {
+    Ok(Json(read_account(jobs.paths())?))
+}
+
+/// Forgets the account, the following jobs log in anonymously.
+async fn logout_handler(State(jobs): State<JobManager>) -> AppResult<StatusCode> {
+    Ok(match remove_account(jobs.paths())? {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    })
+}
+
+/// Expects a `login` message first, so the password stays out of the URL, then attaches to the
+/// login job. Prompts for Steam Guard codes are relayed to the client.
+async fn login_handler(State(jobs): State<JobManager>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(|ws| login(ws, jobs))
+}
+
+#[instrument(skip_all)]
+async fn login(mut ws: WebSocket, jobs: JobManager) {
+    let (username, password) = match ws.recv().await {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+            Ok(ClientMessage::Login { username, password })
+                if !username.is_empty() && !username.contains(char::is_whitespace) =>
+            {
+                (username, password)
             }
-            Err(err) => {
-                let _ = tx
-                    .send(Err(std::io::Error::new(err.kind(), err.to_string())))
-                    .await;
-                error!("while piping:: {}", err);
-                break;
+            _ => {
+                let reason = "expected a login message with a username";
+                return close(ws, close_code::POLICY, reason.to_string()).await;
             }
+        },
+        _ => {
+            debug!("client left before logging in");
+            return;
+        }
+    };
+    match jobs.start(UpdateType::Login { username, password }) {
+        Ok(job) => attach(ws, job).await,
+        Err(err @ SteamCMDError::Busy(_)) => close(ws, close_code::AGAIN, err.to_string()).await,
+        Err(err) => {
+            warn!("failed to start the login job: {:?}", err);
+            close(ws, close_code::ERROR, err.to_string()).await
         }
     }
+}
 
-    debug!("waiting for child exit");
-    let res = child.wait().await;
-    debug!("child exit ret {:?}", res);
+async fn close(mut ws: WebSocket, code: u16, reason: String) {
+    let frame = CloseFrame {
+        code,
+        reason: reason.into(),
+    };
+    let _ = ws.send(Message::Close(Some(frame))).await;
+}
+
+/// Sent by clients as JSON text, tagged like [`super::UpdateSteamMessage`].
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Cancel,
+    /// Starts a login job, only on `/login`.
+    Login {
+        username: String,
+        password: Option<Secret>,
+    },
+    /// Starts an update of a branch with a password, only on `/update?branch_password=true`.
+    BranchPassword {
+        password: Secret,
+    },
+    /// Answers a [`super::parser::Prompt`].
+    Input {
+        text: Secret,
+    },
+}
+
+fn to_message(event: JobEvent) -> Option<Message> {
+    let msg = match event {
+        JobEvent::Output(data) => return Some(Message::Binary(data.to_vec())),
+        JobEvent::Message(msg) => msg,
+        JobEvent::Finished(result) => UpdateSteamMessage::Finished { result },
+    };
+    Some(Message::Text(serde_json::to_string(&msg).ok()?))
+}
+
+/// Closes normally unless SteamCMD failed.
+fn close_frame(result: &JobResult) -> CloseFrame<'static> {
+    let (code, reason) = match &result.status {
+        JobStatus::Running => (close_code::NORMAL, "still running".to_string()),
+        JobStatus::Exited { code: Some(0), .. } => (close_code::NORMAL, "success".to_string()),
+        JobStatus::Cancelled => (close_code::NORMAL, "cancelled".to_string()),
+        JobStatus::Exited {
+            code: Some(code), ..
+        } => (close_code::ERROR, format!("SteamCMD exited with {}", code)),
+        JobStatus::Exited {
+            signal: Some(signal),
+            ..
+        } => (
+            close_code::ERROR,
+            format!("SteamCMD killed by signal {}", signal),
+        ),
+        JobStatus::Exited { .. } => (close_code::ERROR, "SteamCMD exited".to_string()),
+        JobStatus::Failed { .. } => (close_code::ERROR, "failed to run SteamCMD".to_string()),
+    };
+    CloseFrame {
+        code,
+        reason: reason.into(),
+    }
+}
 
-    drop(tx);
-    line_reader.await.unwrap();
-    debug!("line_reader joined");
+#[instrument(skip_all, fields(id = job.id))]
+async fn attach(mut ws: WebSocket, job: Arc<Job>) {
+    let (replay, rx) = job.subscribe();
+    for msg in replay.into_iter().filter_map(to_message) {
+        if ws.send(msg).await.is_err() {
+            debug!("client left during replay");
+            return;
+        }
+    }
+
+    if let Some(mut rx) = rx {
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Err(RecvError::Closed) => break,
+                    Ok(event) => {
+                        let finished = matches!(event, JobEvent::Finished(_));
+                        let Some(msg) = to_message(event) else { continue };
+                        if ws.send(msg).await.is_err() {
+                            debug!("client left");
+                            return;
+                        }
+                        if finished {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => warn!("client skipped {} events", n),
+                },
+                msg = ws.recv() => match msg {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                        Ok(ClientMessage::Cancel) => {
+                            job.cancel();
+                        }
+                        Ok(ClientMessage::Input { text }) => {
+                            if !job.input(text.expose()).await {
+                                warn!("the job takes no input");
+                            }
+                        }
+                        Ok(msg) => warn!("unexpected message from client: {:?}", msg),
+                        Err(err) => warn!("unknown message from client: {}", err),
+                    },
+                    Some(Ok(_)) => {}
+                    _ => {
+                        debug!("client left");
+                        return;
+                    }
+                },
+            }
+        }
+    }
 
-    ws.lock()
-        .await
-        .send(axum::extract::ws::Message::Close(None))
-        .await
-        .unwrap();
+    let _ = ws
+        .send(Message::Close(Some(close_frame(&job.result()))))
+        .await;
 }
@@ -0,0 +1,535 @@
+use std::fmt;
+
+use pest::{
+    error::{ErrorVariant, LineColLocation},
+    iterators::Pair,
+    Parser,
+};
+use pest_derive::Parser;
+use serde::{
+    de::{self, DeserializeSeed, IntoDeserializer, Unexpected, Visitor},
+    forward_to_deserialize_any, Deserialize,
+};
+use thiserror::Error;
+
+#[derive(Parser)]
+#[grammar = "steamcmd/vdf.pest"]
+pub struct VdfParser;
+
+/// A value in Valve's KeyValues (VDF) format, as in `.acf` manifests.
+///
+/// `#include` and `#base` directives are not followed, they are read as plain pairs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    /// Pairs in document order, keys may repeat.
+    Object(Vec<(String, Value)>),
+}
+
+/// Where and why a document failed to parse, positions are 1-based.
+#[derive(Error, Debug, Clone)]
+#[error("{line}:{column}: expected {}", expected.join(" or "))]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub expected: Vec<String>,
+}
+
+impl From<pest::error::Error<Rule>> for ParseError {
+    fn from(err: pest::error::Error<Rule>) -> Self {
+        let (line, column) = match err.line_col {
+            LineColLocation::Pos(pos) | LineColLocation::Span(pos, _) => pos,
+        };
+        let expected = match err.variant {
+            ErrorVariant::ParsingError { positives, .. } => {
+                positives.iter().map(|r| format!("{:?}", r)).collect()
+            }
+            ErrorVariant::CustomError { message } => vec![message],
+        };
+        ParseError {
+            line,
+            column,
+            expected,
+        }
+    }
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            Value::Object(_) => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Value)]> {
+        match self {
+            Value::Object(pairs) => Some(pairs),
+            Value::String(_) => None,
+        }
+    }
+
+    /// The first value under `key`, compared case-insensitively like Steam does.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_object()?
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// Follows `path` through nested objects.
+    pub fn pointer(&self, path: &[&str]) -> Option<&Value> {
+        path.iter().try_fold(self, |value, key| value.get(key))
+    }
+}
+
+/// Defines that hold on the Linux server, for evaluating conditions.
+const DEFINES: &[&str] = &["$LINUX", "$POSIX"];
+
+/// Evaluates a condition like `[$WIN32||!$POSIX]`, `&&` binding tighter than `||` as in Source.
+fn condition_holds(condition: &str) -> bool {
+    let condition = condition.trim_start_matches('[').trim_end_matches(']');
+    condition.split("||").any(|all| {
+        all.split("&&").all(|term| {
+            let term = term.trim();
+            match term.strip_prefix('!') {
+                Some(define) => !DEFINES.contains(&define.trim()),
+                None => DEFINES.contains(&term),
+            }
+        })
+    })
+}
+
+/// Reads back `\"`, `\\`, `\n` and `\t`, anything else after `\` is kept as is.
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c @ ('"' | '\\')) => unescaped.push(c),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Parses a whole document, e.g. an `.acf` file, into an object of its top-level pairs.
+///
+/// Pairs whose condition does not hold on Linux are left out.
+pub fn parse(s: &str) -> Result<Value, ParseError> {
+    Ok(Value::Object(
+        VdfParser::parse(Rule::root, s)?
+            .filter(|pair| pair.as_rule() == Rule::pair) // and EOI
+            .filter_map(pair_of)
+            .collect(),
+    ))
+}
+
+/// Parses the first pair of `s`, ignoring whatever follows it and its own condition.
+pub fn parse_first(s: &str) -> Result<(String, Value), ParseError> {
+    let pair = VdfParser::parse(Rule::first, s)?
+        .next()
+        .expect("first always contains a pair");
+    let mut inner = pair.into_inner().filter(|p| p.as_rule() != Rule::condition);
+    let key = string_of(inner.next().unwrap());
+    Ok((key, value_of(inner.next().unwrap())))
+}
+
+/// `None` if a condition of the pair does not hold.
+fn pair_of(pair: Pair<Rule>) -> Option<(String, Value)> {
+    let mut key = None;
+    let mut value = None;
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            Rule::condition if !condition_holds(p.as_str()) => return None,
+            Rule::condition => {}
+            _ if key.is_none() => key = Some(string_of(p)),
+            _ => value = Some(value_of(p)),
+        }
+    }
+    Some((key.unwrap(), value.unwrap()))
+}
+
+fn value_of(pair: Pair<Rule>) -> Value {
+    match pair.as_rule() {
+        Rule::object => Value::Object(pair.into_inner().filter_map(pair_of).collect()),
+        _ => Value::String(string_of(pair)),
+    }
+}
+
+fn string_of(pair: Pair<Rule>) -> String {
+    match pair.as_rule() {
+        Rule::quoted => unescape(pair.into_inner().next().unwrap().as_str()),
+        Rule::unquoted => pair.as_str().to_string(),
+        _ => unreachable!(),
+    }
+}
+
+/// Why a [`Value`] does not fit the type it is deserialized into.
+#[derive(Error, Debug, Clone)]
+#[error("{0}")]
+pub struct DeError(String);
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeError(msg.to_string())
+    }
+}
+
+/// Deserializes a parsed tree, e.g. into a struct mirroring an `.acf` file.
+///
+/// Everything is a string in VDF, so numbers are parsed and booleans are `0` or `1`. Objects can
+/// also be read as sequences of their values, for keys like `"0"`, `"1"`, ...
+pub fn from_value<'de, T: Deserialize<'de>>(value: &'de Value) -> Result<T, DeError> {
+    T::deserialize(value)
+}
+
+/// A string value or key.
+struct Str<'de>(&'de str);
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident),* $(,)?) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+            match self.0.trim().parse() {
+                Ok(n) => visitor.$visit(n),
+                Err(_) => Err(de::Error::invalid_type(Unexpected::Str(self.0), &visitor)),
+            }
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Str<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0.trim() {
+            "1" | "true" => visitor.visit_bool(true),
+            "0" | "false" => visitor.visit_bool(false),
+            _ => Err(de::Error::invalid_type(Unexpected::Str(self.0), &visitor)),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+macro_rules! deserialize_string {
+    ($($method:ident),* $(,)?) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+            match self {
+                Value::String(s) => Str(s).$method(visitor),
+                Value::Object(_) => Err(de::Error::invalid_type(Unexpected::Map, &visitor)),
+            }
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for &'de Value {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self {
+            Value::String(s) => visitor.visit_borrowed_str(s),
+            Value::Object(pairs) => visitor.visit_map(MapAccess {
+                pairs: pairs.iter(),
+                value: None,
+            }),
+        }
+    }
+
+    deserialize_string! {
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_f32,
+        deserialize_f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self {
+            Value::Object(pairs) => visitor.visit_seq(SeqAccess(pairs.iter())),
+            Value::String(s) => Err(de::Error::invalid_type(Unexpected::Str(s), &visitor)),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        match self {
+            Value::String(s) => Str(s).deserialize_enum(name, variants, visitor),
+            Value::Object(_) => Err(de::Error::invalid_type(Unexpected::Map, &visitor)),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct MapAccess<'de> {
+    pairs: std::slice::Iter<'de, (String, Value)>,
+    value: Option<&'de Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, DeError> {
+        match self.pairs.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Str(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeError> {
+        seed.deserialize(self.value.take().expect("next_key_seed is called first"))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.pairs.len())
+    }
+}
+
+struct SeqAccess<'de>(std::slice::Iter<'de, (String, Value)>);
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = DeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, DeError> {
+        self.0
+            .next()
+            .map(|(_, value)| seed.deserialize(value))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::steamcmd::{parse_app_info, AppManifest};
+
+    const MANIFEST: &str = include_str!("../../tests/fixtures/steamcmd/appmanifest_2394010.acf");
+    const APP_INFO: &str = include_str!("../../tests/fixtures/steamcmd/app_info_print.log");
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = parse(MANIFEST).unwrap();
+        let app = manifest.get("AppState").unwrap();
+        assert_eq!(app.get("buildid"), Some(&string("13948131")));
+        // keys are case-insensitive
+        assert_eq!(app.get("sizeondisk"), Some(&string("3024869117")));
+        assert_eq!(
+            app.pointer(&["InstalledDepots", "2394012", "manifest"]),
+            Some(&string("8023521617592045962"))
+        );
+        assert_eq!(
+            app.pointer(&["SharedDepots", "228988"]),
+            Some(&string("228980"))
+        );
+        assert_eq!(app.get("UserConfig"), Some(&Value::Object(vec![])));
+    }
+
+    #[test]
+    fn manifest_from_value() {
+        let manifest: AppManifest = from_value(&parse(MANIFEST).unwrap()).unwrap();
+        let app = manifest.app_state;
+        assert_eq!(app.buildid, "13948131");
+        assert_eq!(app.size_on_disk, 3024869117);
+        assert_eq!(app.state_flags, 4);
+        assert_eq!(app.user_config.betakey, None);
+        let depots: Vec<_> = app
+            .installed_depots
+            .iter()
+            .map(|(id, depot)| (id.as_str(), depot.manifest.as_str(), depot.size))
+            .collect();
+        assert_eq!(
+            depots,
+            [
+                ("1006", "5587033981095108078", 72573210),
+                ("2394012", "8023521617592045962", 2952295907),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_first_in_output() {
+        let start = APP_INFO.find("\"2394010\"").unwrap();
+        let (key, info) = parse_first(&APP_INFO[start..]).unwrap();
+        assert_eq!(key, "2394010");
+        assert_eq!(
+            info.pointer(&["common", "name"]),
+            Some(&string("Palworld Dedicated Server"))
+        );
+        assert_eq!(
+            info.pointer(&["depots", "2394012", "manifests", "experimental", "download"]),
+            Some(&string("1139022516"))
+        );
+        // whatever follows the pair is ignored
+        let (key, value) = parse_first("\"a\" \"b\" } garbage {").unwrap();
+        assert_eq!((key.as_str(), value), ("a", string("b")));
+    }
+
+    #[test]
+    fn app_info_from_output() {
+        let info = parse_app_info(APP_INFO).unwrap();
+        let branches: Vec<_> = info
+            .depots
+            .branches
+            .iter()
+            .map(|(name, branch)| (name.as_str(), branch.buildid.as_str(), branch.pwdrequired))
+            .collect();
+        assert_eq!(
+            branches,
+            [
+                ("beta", "13950000", true),
+                ("experimental", "13956102", false),
+                ("public", "13948131", false),
+            ]
+        );
+        let experimental = &info.depots.branches["experimental"];
+        assert_eq!(
+            experimental.description.as_deref(),
+            Some("Experimental builds, may be unstable")
+        );
+        assert_eq!(experimental.timeupdated, Some(1712498015));
+        assert!(parse_app_info("Connecting anonymously to Steam Public...OK").is_err());
+    }
+
+    #[test]
+    fn conditions() {
+        let doc = parse(
+            r#"
+            "a" "windows" [$WIN32]
+            "a" "posix" [!$WIN32]
+            "b" [$X360] { "c" "d" }
+            "e" "f" [!$POSIX]
+            "g" "h" [$WIN32||$LINUX]
+            "i" "j" [$POSIX&&!$LINUX]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            doc,
+            Value::Object(vec![
+                ("a".to_string(), string("posix")),
+                ("g".to_string(), string("h")),
+            ])
+        );
+    }
+
+    #[test]
+    fn escapes_and_comments() {
+        let doc = parse(
+            r#"
+            // a comment
+            "quote" "say \"hi\""
+            "path" "C:\\Games\\PalServer"
+            "lines" "a\nb\tc"
+            "unknown" "\q"
+            unquoted value // another comment
+            "#,
+        )
+        .unwrap();
+        assert_eq!(doc.get("quote"), Some(&string("say \"hi\"")));
+        assert_eq!(doc.get("path"), Some(&string("C:\\Games\\PalServer")));
+        assert_eq!(doc.get("lines"), Some(&string("a\nb\tc")));
+        assert_eq!(doc.get("unknown"), Some(&string("\\q")));
+        assert_eq!(doc.get("unquoted"), Some(&string("value")));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = parse("\"a\"\n{\n\t\"b\" \"c\"\n").unwrap_err();
+        assert_eq!((err.line, err.column), (4, 1));
+        assert!(from_value::<AppManifest>(&parse("\"AppState\" \"x\"").unwrap()).is_err());
+    }
+}